@@ -0,0 +1,184 @@
+//
+//  dir         WJ124
+//  background.rs
+//
+
+// Find out whether the terminal has a light or dark background
+// The terminal is asked with the OSC 11 escape sequence; terminals that
+// do not answer are given a short while, and then we fall back to
+// looking at $COLORFGBG (set by some terminals, like rxvt and Konsole)
+
+#[cfg(unix)]
+use std::time::Duration;
+
+// how long to wait for the terminal to answer
+#[cfg(unix)]
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    Light,
+    Dark,
+}
+
+impl Background {
+    pub fn from_name(name: &str) -> Option<Background> {
+        match name {
+            "light" => Some(Background::Light),
+            "dark" => Some(Background::Dark),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Background::Light => "light",
+            Background::Dark => "dark",
+        }
+    }
+}
+
+// Returns background of the terminal, or None if it can not be told
+pub fn detect() -> Option<Background> {
+    query_terminal().or_else(from_colorfgbg)
+}
+
+// $COLORFGBG is like "15;0": foreground;background
+// or "15;default;0"; the background is always the last field
+fn from_colorfgbg() -> Option<Background> {
+    let value = std::env::var("COLORFGBG").ok()?;
+    let bg = value.rsplit(';').next()?.parse::<u32>().ok()?;
+    // colors 0-6 and 8 are dark; 7 (light gray) and 9-15 are light
+    match bg {
+        0..=6 | 8 => Some(Background::Dark),
+        7 | 9..=15 => Some(Background::Light),
+        _ => None,
+    }
+}
+
+// ask the terminal for its background color
+#[cfg(unix)]
+fn query_terminal() -> Option<Background> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    let fd = tty.as_raw_fd();
+
+    // a background job may not talk to the terminal; it would be stopped
+    // SAFETY: fd is an open file descriptor
+    if unsafe { libc::tcgetpgrp(fd) != libc::getpgrp() } {
+        return None;
+    }
+
+    // put the terminal in raw mode, so that the answer is not echoed
+    // and can be read without waiting for a newline
+    let mut saved = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: fd is an open file descriptor, saved is valid memory
+    if unsafe { libc::tcgetattr(fd, saved.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: tcgetattr() succeeded and filled in saved
+    let saved = unsafe { saved.assume_init() };
+    let mut raw = saved;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    // SAFETY: fd is an open file descriptor, raw is a valid termios
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+
+    let answer = if tty.write_all(b"\x1b]11;?\x07").is_ok() && tty.flush().is_ok() {
+        read_answer(fd)
+    } else {
+        None
+    };
+
+    // an answer that comes in late must not end up in the shell's input
+    // SAFETY: fd is an open file descriptor, saved is a valid termios
+    unsafe {
+        libc::tcflush(fd, libc::TCIFLUSH);
+        libc::tcsetattr(fd, libc::TCSANOW, &saved);
+    }
+    parse_answer(&answer?)
+}
+
+#[cfg(not(unix))]
+fn query_terminal() -> Option<Background> {
+    None
+}
+
+// read the answer, which ends in BEL or ST (ESC \)
+// Gives up after the timeout; the terminal may not answer at all
+#[cfg(unix)]
+fn read_answer(fd: std::os::fd::RawFd) -> Option<String> {
+    use std::time::Instant;
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut answer = Vec::new();
+
+    loop {
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: pollfd is valid memory for one struct pollfd
+        let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            return None;
+        }
+
+        let mut buf = [0u8; 64];
+        // SAFETY: buf is valid memory of the given size
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n <= 0 {
+            return None;
+        }
+        answer.extend_from_slice(&buf[..n as usize]);
+
+        if answer.ends_with(b"\x07") || answer.ends_with(b"\x1b\\") {
+            return Some(String::from_utf8_lossy(&answer).to_string());
+        }
+        if answer.len() > 1024 {
+            // this is not the answer we are looking for
+            return None;
+        }
+    }
+}
+
+// answer looks like "ESC ] 11 ; rgb:RRRR/GGGG/BBBB BEL"
+// Each component has one to four hex digits
+#[cfg(unix)]
+fn parse_answer(answer: &str) -> Option<Background> {
+    let start = answer.find("rgb:")? + 4;
+    let rgb = answer[start..].trim_end_matches(['\x07', '\x1b', '\\']);
+
+    let mut components = Vec::new();
+    for hex in rgb.split('/') {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        components.push(value as f64 / max as f64);
+    }
+    if components.len() != 3 {
+        return None;
+    }
+
+    // perceived brightness
+    let luma = 0.299 * components[0] + 0.587 * components[1] + 0.114 * components[2];
+    if luma > 0.5 {
+        Some(Background::Light)
+    } else {
+        Some(Background::Dark)
+    }
+}
+
+// EOB
@@ -0,0 +1,72 @@
+//
+//  dir         WJ124
+//  cache.rs
+//
+
+// in-process cache of directory listings
+//
+// A cached listing is valid for as long as the modification time of
+// the directory itself does not change; creating, removing or renaming
+// an entry updates the mtime of the directory.
+// Mind the tradeoffs: changes *inside* entries (size, mtime, permissions
+// of files) do not update the mtime of the directory, so a cached listing
+// may show stale metadata. Also, filesystems with coarse timestamps may
+// miss changes made within the same second. The cache is therefore only
+// used when asked for with --cache, and never with --watch
+
+use crate::entry::{Dereference, Entry};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+struct CachedListing {
+    mtime: SystemTime,
+    entries: Vec<Entry>,
+}
+
+lazy_static! {
+    // entries differ depending on whether symbolic links are followed
+    static ref CACHE: Mutex<HashMap<(PathBuf, Dereference), CachedListing>> =
+        Mutex::new(HashMap::new());
+}
+
+fn dir_mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+// Returns cached entries for directory, if still valid
+pub fn lookup(path: &Path, dereference: Dereference) -> Option<Vec<Entry>> {
+    let mtime = dir_mtime(path)?;
+
+    let cache = CACHE.lock().expect("failed to lock mutex on listing cache");
+    let listing = cache.get(&(path.to_path_buf(), dereference))?;
+    if listing.mtime != mtime {
+        // directory has changed
+        return None;
+    }
+    Some(listing.entries.clone())
+}
+
+// store entries for directory
+// The mtime should be taken (with stamp()) before reading the directory,
+// so that a change made while reading invalidates the cached entries
+pub fn store(path: &Path, dereference: Dereference, mtime: SystemTime, entries: &[Entry]) {
+    let mut cache = CACHE.lock().expect("failed to lock mutex on listing cache");
+    cache.insert(
+        (path.to_path_buf(), dereference),
+        CachedListing {
+            mtime,
+            entries: entries.to_vec(),
+        },
+    );
+}
+
+// Returns mtime of directory, for use as key with store()
+pub fn stamp(path: &Path) -> Option<SystemTime> {
+    dir_mtime(path)
+}
+
+// EOB
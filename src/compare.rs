@@ -0,0 +1,254 @@
+//
+//  dir         WJ124
+//  compare.rs
+//
+
+// --diff shows how two directories differ, like a backup and its original
+// Entries that are only in the first directory are marked '<',
+// entries that are only in the second directory are marked '>',
+// and entries that are in both but differ in type, size or time are marked '|'
+// Entries that are the same are not shown
+
+use crate::entry::Entry;
+use crate::{
+    classify, compare_names, display_link_dest, filter_hidden, list_dir, metadata_filetype,
+    report_error, should_descend, Settings, FT_FILE,
+};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const END_COLOR: &str = "\x1b[0m";
+
+// an entry that differs, with its path relative to the compared directories
+struct Difference {
+    marker: char,
+    path: PathBuf,
+    entry: Entry,
+}
+
+// Returns number of errors
+// The differences are printed only after both trees have been read, so
+// that errors do not end up in between. When a directory can not be read,
+// there is nothing to compare it with; it is reported, but not marked
+// as a difference
+pub(crate) fn compare_dirs(dir_a: &Path, dir_b: &Path, settings: &Settings) -> u32 {
+    let mut differences = Vec::new();
+    let errors = compare_subdirs(dir_a, dir_b, Path::new(""), 1, &mut differences, settings);
+
+    for difference in differences.iter() {
+        print_line(difference, settings);
+    }
+    errors
+}
+
+fn compare_subdirs(
+    dir_a: &Path,
+    dir_b: &Path,
+    relative: &Path,
+    depth: usize,
+    differences: &mut Vec<Difference>,
+    settings: &Settings,
+) -> u32 {
+    let (mut entries_a, mut entries_b, mut errors) =
+        match (read_entries(dir_a, settings), read_entries(dir_b, settings)) {
+            (Ok((entries_a, errors_a)), Ok((entries_b, errors_b))) => {
+                (entries_a, entries_b, errors_a + errors_b)
+            }
+            (result_a, result_b) => {
+                let mut errors = 0;
+                for (dir, result) in [(dir_a, result_a), (dir_b, result_b)] {
+                    if let Err(e) = result {
+                        report_error(dir, &e, settings);
+                        errors += 1;
+                    }
+                }
+                return errors;
+            }
+        };
+
+    // all names, in the order in which they would be listed
+    let mut names = entries_a.keys().cloned().collect::<Vec<OsString>>();
+    names.extend(
+        entries_b
+            .keys()
+            .filter(|x| !entries_a.contains_key(*x))
+            .cloned(),
+    );
+    names.sort_by(|a, b| compare_names(a, b, settings));
+
+    let max_depth_reached = settings.depth.is_some_and(|max| depth >= max);
+
+    for name in names {
+        let path = relative.join(&name);
+        // a differing entry is shown as it is in the first directory
+        let (marker, entry) = match (entries_a.remove(&name), entries_b.remove(&name)) {
+            (Some(a), None) => ('<', a),
+            (None, Some(b)) => ('>', b),
+            (Some(a), Some(b)) => {
+                if differs(&a, &b) {
+                    ('|', a)
+                } else {
+                    if settings.recursive
+                        && !max_depth_reached
+                        && should_descend(&a, settings)
+                        && should_descend(&b, settings)
+                    {
+                        errors += compare_subdirs(
+                            &a.path(),
+                            &b.path(),
+                            &path,
+                            depth + 1,
+                            differences,
+                            settings,
+                        );
+                    }
+                    continue;
+                }
+            }
+            (None, None) => continue,
+        };
+        differences.push(Difference {
+            marker,
+            path,
+            entry,
+        });
+    }
+    errors
+}
+
+// Returns entries of directory by name, plus number of errors
+fn read_entries(dir: &Path, settings: &Settings) -> io::Result<(HashMap<OsString, Entry>, u32)> {
+    let (entries, n_errors) = list_dir(dir, settings)?;
+    let entries = entries
+        .into_iter()
+        .filter(|x| filter_hidden(x, settings))
+        .map(|x| (x.name.clone(), x))
+        .collect();
+    Ok((entries, n_errors))
+}
+
+// Returns true if the entries differ
+// Directories are compared by their contents (with -R), not by their size or time
+fn differs(a: &Entry, b: &Entry) -> bool {
+    let filetype = metadata_filetype(&a.metadata);
+    if filetype != metadata_filetype(&b.metadata) {
+        return true;
+    }
+    if filetype == FT_FILE {
+        return a.size() != b.size() || a.mtime() != b.mtime();
+    }
+    a.link_dest != b.link_dest
+}
+
+fn print_line(difference: &Difference, settings: &Settings) {
+    let mut name = display_link_dest(&difference.path, settings);
+    if let Some(token) = classify(&difference.entry, settings) {
+        name.push_str(token);
+    }
+    if settings.color {
+        let color = match difference.marker {
+            '<' => RED,
+            '>' => GREEN,
+            _ => YELLOW,
+        };
+        println!("{}{} {}{}", color, difference.marker, name, END_COLOR);
+    } else {
+        println!("{} {}", difference.marker, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{set_mtime, TempDir};
+    use std::time::{Duration, SystemTime};
+
+    // two directories with known differences
+    fn fixtures() -> (TempDir, TempDir) {
+        let time = SystemTime::now() - Duration::from_secs(3600);
+        let a = TempDir::new();
+        let b = TempDir::new();
+        for (tmp, changed_size) in [(&a, 10), (&b, 20)] {
+            for (name, size) in [
+                ("same.txt", 10),
+                ("changed.txt", changed_size),
+                ("sub/inner.txt", 5),
+            ] {
+                set_mtime(&tmp.file(name, size), time);
+            }
+        }
+        a.file("only_a.txt", 1);
+        a.file("sub/only_sub_a.txt", 1);
+        b.file("only_b.txt", 1);
+        b.dir("only_b_dir");
+        (a, b)
+    }
+
+    // Returns markers and paths of the differences, and number of errors
+    fn differences(a: &Path, b: &Path, settings: &Settings) -> (Vec<String>, u32) {
+        let mut differences = Vec::new();
+        let errors = compare_subdirs(a, b, Path::new(""), 1, &mut differences, settings);
+        let lines = differences
+            .iter()
+            .map(|x| format!("{} {}", x.marker, x.path.display()))
+            .collect();
+        (lines, errors)
+    }
+
+    #[test]
+    fn known_differences() {
+        let (a, b) = fixtures();
+        let settings = Settings::new();
+
+        let (lines, errors) = differences(a.path(), b.path(), &settings);
+        assert_eq!(errors, 0);
+        assert_eq!(
+            lines,
+            [
+                "| changed.txt",
+                "< only_a.txt",
+                "> only_b.txt",
+                "> only_b_dir"
+            ]
+        );
+
+        let recursive = Settings {
+            recursive: true,
+            ..Settings::new()
+        };
+        let (lines, errors) = differences(a.path(), b.path(), &recursive);
+        assert_eq!(errors, 0);
+        assert_eq!(
+            lines,
+            [
+                "| changed.txt",
+                "< only_a.txt",
+                "> only_b.txt",
+                "> only_b_dir",
+                "< sub/only_sub_a.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn unreadable_directory_is_not_a_difference() {
+        let (a, _) = fixtures();
+        let missing = a.path().join("missing");
+        let settings = Settings::new();
+
+        // it would be wrong to mark everything in the other directory
+        let (lines, errors) = differences(a.path(), &missing, &settings);
+        assert_eq!(errors, 1);
+        assert!(lines.is_empty());
+        let (lines, errors) = differences(&missing, a.path(), &settings);
+        assert_eq!(errors, 1);
+        assert!(lines.is_empty());
+    }
+}
+
+// EOB
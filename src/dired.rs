@@ -0,0 +1,172 @@
+//
+//  dir         WJ124
+//  dired.rs
+//
+
+// output for Emacs dired, like `ls --dired`
+// Every line is indented by two spaces. At the end, the byte offsets
+// of all filenames are printed in a //DIRED// trailer, so that Emacs
+// can find the names without having to parse the lines. Likewise,
+// //SUBDIRED// holds the offsets of the directory names in the headers
+
+use lazy_static::lazy_static;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+const INDENT: &str = "  ";
+
+#[derive(Default)]
+struct Output {
+    // number of bytes written so far
+    offset: usize,
+    // (begin, end) of every filename
+    names: Vec<(usize, usize)>,
+    // (begin, end) of every directory name in a header
+    subdirs: Vec<(usize, usize)>,
+}
+
+lazy_static! {
+    static ref OUTPUT: Mutex<Output> = Mutex::new(Output::default());
+}
+
+impl Output {
+    fn write_str(&mut self, out: &mut impl Write, s: &str) {
+        self.offset += s.len();
+        let _ = out.write_all(s.as_bytes());
+    }
+
+    fn line(&mut self, out: &mut impl Write, line: &str) {
+        self.write_str(out, INDENT);
+        self.write_str(out, line);
+        self.write_str(out, "\n");
+    }
+
+    fn blank_line(&mut self, out: &mut impl Write) {
+        self.write_str(out, "\n");
+    }
+
+    fn entry(&mut self, out: &mut impl Write, before: &str, name: &str, after: &str) {
+        self.write_str(out, INDENT);
+        self.write_str(out, before);
+        let begin = self.offset;
+        self.write_str(out, name);
+        self.names.push((begin, self.offset));
+        self.write_str(out, after);
+        self.write_str(out, "\n");
+    }
+
+    fn header(&mut self, out: &mut impl Write, path: &str) {
+        self.write_str(out, INDENT);
+        let begin = self.offset;
+        self.write_str(out, path);
+        self.subdirs.push((begin, self.offset));
+        self.write_str(out, ":\n");
+    }
+
+    fn trailer(&self, out: &mut impl Write) {
+        let mut buf = String::new();
+        if !self.names.is_empty() {
+            buf.push_str(&format!("//DIRED//{}\n", format_offsets(&self.names)));
+        }
+        if !self.subdirs.is_empty() {
+            buf.push_str(&format!("//SUBDIRED//{}\n", format_offsets(&self.subdirs)));
+        }
+        buf.push_str("//DIRED-OPTIONS// --quoting-style=literal\n");
+
+        let _ = out.write_all(buf.as_bytes());
+        let _ = out.flush();
+    }
+}
+
+fn lock_output() -> std::sync::MutexGuard<'static, Output> {
+    OUTPUT.lock().expect("failed to lock mutex on dired output")
+}
+
+// print a line that holds no filename, like the column titles
+pub fn print_line(line: &str) {
+    lock_output().line(&mut io::stdout(), line);
+}
+
+pub fn print_blank_line() {
+    lock_output().blank_line(&mut io::stdout());
+}
+
+// print a line of the long listing; only the name is recorded
+// `before` holds the columns, `after` the classify indicator and link destination
+pub fn print_entry(before: &str, name: &str, after: &str) {
+    lock_output().entry(&mut io::stdout(), before, name, after);
+}
+
+// print directory header, like "  src:"
+pub fn print_header(path: &str) {
+    lock_output().header(&mut io::stdout(), path);
+}
+
+// print the trailer with the offsets
+// Names are shown as they are, so the quoting style is always literal
+pub fn print_trailer() {
+    lock_output().trailer(&mut io::stdout());
+}
+
+fn format_offsets(offsets: &[(usize, usize)]) -> String {
+    offsets
+        .iter()
+        .map(|(begin, end)| format!(" {} {}", begin, end))
+        .collect::<String>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the (begin, end) pairs from a trailer line
+    fn parse_offsets(line: &str, tag: &str) -> Vec<(usize, usize)> {
+        let numbers = line
+            .strip_prefix(tag)
+            .unwrap()
+            .split_whitespace()
+            .map(|x| x.parse::<usize>().unwrap())
+            .collect::<Vec<usize>>();
+        numbers.chunks(2).map(|x| (x[0], x[1])).collect()
+    }
+
+    #[test]
+    fn offsets_point_at_the_names() {
+        let mut output = Output::default();
+        let mut buf = Vec::new();
+        output.header(&mut buf, "/tmp/sub dir");
+        output.line(&mut buf, "total 3");
+        output.entry(&mut buf, "-rw-r--r--  12  ", "plain.txt", "");
+        // multibyte names count in bytes, not in characters
+        output.entry(&mut buf, "-rw-r--r--   3  ", "café ünïcode", "");
+        output.entry(&mut buf, "lrwxrwxrwx   6  ", "link", " -> plain.txt");
+        output.blank_line(&mut buf);
+        let listing_len = buf.len();
+        output.trailer(&mut buf);
+
+        let text = String::from_utf8(buf).unwrap();
+        let trailer = text[listing_len..].lines().collect::<Vec<&str>>();
+        assert_eq!(trailer.len(), 3);
+        assert_eq!(trailer[2], "//DIRED-OPTIONS// --quoting-style=literal");
+
+        let names = parse_offsets(trailer[0], "//DIRED//")
+            .into_iter()
+            .map(|(begin, end)| &text[begin..end])
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["plain.txt", "café ünïcode", "link"]);
+
+        let subdirs = parse_offsets(trailer[1], "//SUBDIRED//");
+        assert_eq!(subdirs, [(2, 14)]);
+        assert_eq!(&text[2..14], "/tmp/sub dir");
+    }
+
+    #[test]
+    fn trailer_without_names() {
+        let output = Output::default();
+        let mut buf = Vec::new();
+        output.trailer(&mut buf);
+        assert_eq!(buf, b"//DIRED-OPTIONS// --quoting-style=literal\n");
+    }
+}
+
+// EOB
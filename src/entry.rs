@@ -10,6 +10,8 @@ use std::fs::{DirEntry, Metadata};
 use std::io;
 use std::path::{Path, PathBuf};
 
+#[cfg(unix)]
+use lazy_static::lazy_static;
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
 
@@ -25,15 +27,50 @@ pub const S_ISUID: u32 = 0o4000;
 pub const S_ISGID: u32 = 0o2000;
 pub const S_ISVTX: u32 = 0o1000;
 
-#[derive(Debug)]
+// how to treat symbolic links
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dereference {
+    // show the symbolic link itself
+    Never,
+    // show the file that the link points to; a broken link is an error
+    Follow,
+    // show the file that the link points to; a broken link is shown as link
+    FollowKeepBroken,
+}
+
+// which timestamp is shown, and sorted on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeSource {
+    Modified,
+    Accessed,
+    Created,
+}
+
+#[derive(Debug, Clone)]
 pub struct Entry {
     pub name: OsString,
+    // the directory that the entry is in
+    pub parent: PathBuf,
     pub metadata: Metadata,
     pub link_dest: Option<PathBuf>,
+    // number of entries in a directory (only used with --dir-count)
+    pub dir_count: Option<usize>,
+    // total size of a directory's contents, or error message (only used with --dir-size)
+    pub recursive_size: Option<Result<u64, String>>,
+    // symlink points at a directory (only used with --symlinks-as-target-type)
+    pub link_to_dir: bool,
+    // metadata of the other end of a followed symbolic link,
+    // and which attributes are taken from it (only used with --dereference=LIST)
+    pub other_metadata: Option<Metadata>,
+    pub size_from_other: bool,
+    pub time_from_other: bool,
+    // the directory that the entry is in, relative to the directory
+    // given on the command-line (only used with --relative)
+    pub relative_parent: Option<PathBuf>,
 }
 
 impl Entry {
-    pub fn from_dir_entry(d: &DirEntry) -> Result<Entry, io::Error> {
+    pub fn from_dir_entry(d: &DirEntry, dereference: Dereference) -> Result<Entry, io::Error> {
         let path = d.path();
         let some_filename = path.file_name();
         if some_filename.is_none() {
@@ -43,8 +80,35 @@ impl Entry {
             ));
         }
         let filename = some_filename.unwrap().to_os_string();
+        let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
 
+        // note, DirEntry::metadata() does not traverse symlinks
         let metadata = d.metadata()?;
+        if metadata.is_symlink() && dereference != Dereference::Never {
+            match fs::metadata(&path) {
+                Ok(target_metadata) => {
+                    return Ok(Entry {
+                        name: filename,
+                        parent,
+                        metadata: target_metadata,
+                        link_dest: None,
+                        dir_count: None,
+                        recursive_size: None,
+                        link_to_dir: false,
+                        other_metadata: Some(metadata),
+                        size_from_other: false,
+                        time_from_other: false,
+                        relative_parent: None,
+                    });
+                }
+                Err(err) => {
+                    if dereference == Dereference::Follow {
+                        return Err(err);
+                    }
+                    // else: broken link; keep showing the link itself
+                }
+            }
+        }
         let link_dest = if metadata.is_symlink() {
             Some(fs::read_link(path)?)
         } else {
@@ -53,21 +117,56 @@ impl Entry {
 
         Ok(Entry {
             name: filename,
+            parent,
             metadata,
             link_dest,
+            dir_count: None,
+            recursive_size: None,
+            link_to_dir: false,
+            other_metadata: None,
+            size_from_other: false,
+            time_from_other: false,
+            relative_parent: None,
         })
     }
 
-    pub fn from_path(path: &Path) -> Result<Entry, io::Error> {
-        let some_filename = path.file_name();
-        if some_filename.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "invalid filename",
-            ));
+    pub fn from_path(path: &Path, dereference: Dereference) -> Result<Entry, io::Error> {
+        // a path like "." or "/" has no filename; then the name is the whole path
+        let (filename, parent) = match path.file_name() {
+            Some(filename) => (
+                filename.to_os_string(),
+                path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            ),
+            None => (path.as_os_str().to_os_string(), PathBuf::new()),
+        };
+        // note, do not traverse symlinks unless asked to
+        // the size of a symlink is the length of the path it points at
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_symlink() && dereference != Dereference::Never {
+            match fs::metadata(path) {
+                Ok(target_metadata) => {
+                    return Ok(Entry {
+                        name: filename,
+                        parent,
+                        metadata: target_metadata,
+                        link_dest: None,
+                        dir_count: None,
+                        recursive_size: None,
+                        link_to_dir: false,
+                        other_metadata: Some(metadata),
+                        size_from_other: false,
+                        time_from_other: false,
+                        relative_parent: None,
+                    });
+                }
+                Err(err) => {
+                    if dereference == Dereference::Follow {
+                        return Err(err);
+                    }
+                    // else: broken link; keep showing the link itself
+                }
+            }
         }
-        let filename = some_filename.unwrap().to_os_string();
-        let metadata = fs::metadata(path)?;
         let link_dest = if metadata.is_symlink() {
             Some(fs::read_link(path)?)
         } else {
@@ -76,19 +175,99 @@ impl Entry {
 
         Ok(Entry {
             name: filename,
+            parent,
             metadata,
             link_dest,
+            dir_count: None,
+            recursive_size: None,
+            link_to_dir: false,
+            other_metadata: None,
+            size_from_other: false,
+            time_from_other: false,
+            relative_parent: None,
         })
     }
 
+    // Returns path of the entry, relative to where we started listing
+    pub fn path(&self) -> PathBuf {
+        self.parent.join(&self.name)
+    }
+
+    // Returns path of the entry, relative to the directory given on the command-line
+    // This is only different from the name with --relative
+    pub fn relative_path(&self) -> PathBuf {
+        match &self.relative_parent {
+            Some(relative_parent) => relative_parent.join(&self.name),
+            None => PathBuf::from(&self.name),
+        }
+    }
+
+    // Returns size in bytes
+    pub fn size(&self) -> u64 {
+        match &self.other_metadata {
+            Some(other) if self.size_from_other => other.len(),
+            _ => self.metadata.len(),
+        }
+    }
+
     pub fn mtime(&self) -> DateTime<Local> {
-        if let Ok(t) = self.metadata.modified() {
+        if let Ok(t) = self.time_metadata().modified() {
             t.into()
         } else {
             Local.timestamp_opt(0, 0).unwrap()
         }
     }
 
+    // Returns the selected timestamp
+    // Not every platform and filesystem records access and creation times;
+    // then the modification time is used
+    pub fn time(&self, source: TimeSource) -> DateTime<Local> {
+        let t = match source {
+            TimeSource::Modified => return self.mtime(),
+            TimeSource::Accessed => self.time_metadata().accessed(),
+            TimeSource::Created => self.time_metadata().created(),
+        };
+        t.map(|t| t.into()).unwrap_or_else(|_| self.mtime())
+    }
+
+    // Returns the metadata that timestamps are taken from
+    fn time_metadata(&self) -> &Metadata {
+        match &self.other_metadata {
+            Some(other) if self.time_from_other => other,
+            _ => &self.metadata,
+        }
+    }
+
+    // Returns time of last access, if known
+    pub fn atime(&self) -> Option<DateTime<Local>> {
+        self.metadata.accessed().ok().map(|t| t.into())
+    }
+
+    // Returns time of last status change (inode change), if known
+    #[cfg(unix)]
+    pub fn ctime(&self) -> Option<DateTime<Local>> {
+        Local
+            .timestamp_opt(self.metadata.ctime(), self.metadata.ctime_nsec() as u32)
+            .single()
+    }
+
+    // there is no status change time; the creation time is the closest thing
+    #[cfg(not(unix))]
+    pub fn ctime(&self) -> Option<DateTime<Local>> {
+        self.metadata.created().ok().map(|t| t.into())
+    }
+
+    // Returns inode number
+    #[cfg(unix)]
+    pub fn inode(&self) -> Option<u64> {
+        Some(self.metadata.ino())
+    }
+
+    #[cfg(not(unix))]
+    pub fn inode(&self) -> Option<u64> {
+        None
+    }
+
     #[cfg(all(unix, not(target_os = "macos")))]
     pub fn is_hidden(&self) -> bool {
         // sucks that we have to convert this entire thing just to look at one first character
@@ -144,6 +323,76 @@ impl Entry {
         first == '.'
     }
 
+    // Returns number of hard links
+    #[cfg(unix)]
+    pub fn nlink(&self) -> u64 {
+        self.metadata.nlink()
+    }
+
+    #[cfg(not(unix))]
+    pub fn nlink(&self) -> u64 {
+        1
+    }
+
+    // Returns major, minor device number of a device file
+    #[cfg(all(unix, not(target_os = "macos")))]
+    pub fn device_number(&self) -> Option<(u64, u64)> {
+        let rdev = self.metadata.rdev();
+        let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+        let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+        Some((major, minor))
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn device_number(&self) -> Option<(u64, u64)> {
+        let rdev = self.metadata.rdev();
+        let major = (rdev >> 24) & 0xff;
+        let minor = rdev & 0xffffff;
+        Some((major, minor))
+    }
+
+    #[cfg(not(unix))]
+    pub fn device_number(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    // Returns number of bytes allocated on disk, if known
+    #[cfg(unix)]
+    pub fn allocated_size(&self) -> Option<u64> {
+        // st_blocks is always in units of 512 bytes
+        Some(self.metadata.blocks() * 512)
+    }
+
+    #[cfg(not(unix))]
+    pub fn allocated_size(&self) -> Option<u64> {
+        None
+    }
+
+    // A file is sparse when it has holes;
+    // at least one whole block of it is not allocated on disk
+    #[cfg(unix)]
+    pub fn is_sparse(&self) -> bool {
+        if !self.metadata.is_file() {
+            return false;
+        }
+        let allocated = self.metadata.blocks() * 512;
+        allocated + self.metadata.blksize() <= self.metadata.len()
+    }
+
+    #[cfg(windows)]
+    pub fn is_sparse(&self) -> bool {
+        use std::os::windows::fs::MetadataExt;
+
+        const FILE_ATTRIBUTE_SPARSE_FILE: u32 = 0x200;
+
+        self.metadata.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE != 0
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn is_sparse(&self) -> bool {
+        false
+    }
+
     #[cfg(unix)]
     pub fn is_exec(&self) -> bool {
         let perms = self.metadata.mode() & 0o111;
@@ -156,6 +405,34 @@ impl Entry {
         lossy_name.ends_with(".exe") || lossy_name.ends_with(".EXE")
     }
 
+    // Returns true if the current user may execute the file
+    // This looks at the permission bits for owner, group or others,
+    // depending on who we are
+    #[cfg(unix)]
+    pub fn is_executable_by_current_user(&self) -> bool {
+        if !self.metadata.is_file() {
+            return false;
+        }
+        let mode = self.metadata.mode();
+        let euid = unsafe { libc::geteuid() };
+        if euid == 0 {
+            // root may execute if anyone may
+            return mode & 0o111 != 0;
+        }
+        if self.metadata.uid() == euid {
+            return mode & 0o100 != 0;
+        }
+        if is_member_of_group(self.metadata.gid()) {
+            return mode & 0o010 != 0;
+        }
+        mode & 0o001 != 0
+    }
+
+    #[cfg(not(unix))]
+    pub fn is_executable_by_current_user(&self) -> bool {
+        self.is_exec()
+    }
+
     #[cfg(unix)]
     pub fn is_suid(&self) -> bool {
         const S_ISUID: u32 = 0o4000;
@@ -205,4 +482,93 @@ impl Entry {
     }
 }
 
+// Returns true if gid is our effective group, or one of our supplementary groups
+#[cfg(unix)]
+fn is_member_of_group(gid: u32) -> bool {
+    lazy_static! {
+        // the groups do not change while we run; ask only once
+        static ref GROUPS: Vec<libc::gid_t> = current_groups();
+    }
+    GROUPS.contains(&(gid as libc::gid_t))
+}
+
+#[cfg(unix)]
+fn current_groups() -> Vec<libc::gid_t> {
+    let mut groups = vec![unsafe { libc::getegid() }];
+
+    let num = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if num > 0 {
+        let mut buf = vec![0 as libc::gid_t; num as usize];
+        let num = unsafe { libc::getgroups(num, buf.as_mut_ptr()) };
+        if num > 0 {
+            buf.truncate(num as usize);
+            groups.extend(buf);
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_fails_on_broken_link() {
+        let tmp = TempDir::new();
+        let link = tmp.symlink("nonexistent", "dangling");
+
+        assert!(Entry::from_path(&link, Dereference::Follow).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_broken_shows_broken_link_as_link() {
+        let tmp = TempDir::new();
+        let link = tmp.symlink("nonexistent", "dangling");
+
+        let entry = Entry::from_path(&link, Dereference::FollowKeepBroken).unwrap();
+        assert!(entry.metadata.is_symlink());
+        assert_eq!(entry.link_dest, Some(PathBuf::from("nonexistent")));
+        assert!(entry.other_metadata.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_broken_follows_good_link() {
+        let tmp = TempDir::new();
+        tmp.file("target", 10);
+        let link = tmp.symlink("target", "good");
+
+        let entry = Entry::from_path(&link, Dereference::FollowKeepBroken).unwrap();
+        assert!(entry.metadata.is_file());
+        assert_eq!(entry.size(), 10);
+        assert!(entry.other_metadata.unwrap().is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_size_is_length_of_target() {
+        let tmp = TempDir::new();
+        let target = tmp.file(&format!("{}/big", "long_directory_name".repeat(5)), 5000);
+        let target = target.to_string_lossy().to_string();
+        let link = tmp.symlink(&target, "link");
+
+        let entry = Entry::from_path(&link, Dereference::Never).unwrap();
+        assert!(entry.metadata.is_symlink());
+        assert_eq!(entry.size(), target.len() as u64);
+        assert_eq!(entry.link_dest, Some(PathBuf::from(&target)));
+
+        // listed from the directory, it is the same
+        let d = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|x| x.unwrap())
+            .find(|x| x.file_name() == "link")
+            .unwrap();
+        let entry = Entry::from_dir_entry(&d, Dereference::Never).unwrap();
+        assert_eq!(entry.size(), target.len() as u64);
+    }
+}
+
 // EOB
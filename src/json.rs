@@ -0,0 +1,309 @@
+//
+//  dir         WJ124
+//  json.rs
+//
+
+use crate::entry::Entry;
+use crate::{filetype_name, metadata_filetype, summarize};
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// version of the JSON output format
+// bump this whenever the layout of the document changes;
+// adding fields to entries does not break consumers
+// Version 2 puts directory listings in "directories", keyed by path
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Default)]
+struct Document {
+    // listings of directories, keyed by the path of the directory
+    directories: Map<String, Value>,
+    // everything that is not a directory listing: files given on
+    // the command-line, merged listings, and --stat
+    entries: Vec<Value>,
+    errors: Vec<Value>,
+}
+
+lazy_static! {
+    // the document is collected while listing, and printed at the very end
+    static ref DOCUMENT: Mutex<Document> = Mutex::new(Document::default());
+}
+
+impl Document {
+    // add the (already sorted) listing of a directory
+    // With --summary, the directory also gets the number of entries,
+    // and the total size of the files in it
+    fn add_directory(&mut self, path: &Path, entries: &[&Entry], with_summary: bool) {
+        let key = path.to_string_lossy().to_string();
+        let listing = self
+            .directories
+            .entry(key)
+            .or_insert_with(|| json!({"entries": []}));
+
+        // a directory that is listed twice gets the entries twice
+        if let Some(Value::Array(values)) = listing.get_mut("entries") {
+            values.extend(entries.iter().map(|x| entry_to_json(x)));
+        }
+        if with_summary {
+            let summary = summarize(entries);
+            let add = |value: &Value, n: u64| value.as_u64().unwrap_or(0) + n;
+            listing["entry_count"] = json!(add(&listing["entry_count"], entries.len() as u64));
+            listing["total_apparent_bytes"] =
+                json!(add(&listing["total_apparent_bytes"], summary.total_size));
+        }
+    }
+
+    fn add_error(&mut self, path: &Path, err: &dyn Display) {
+        self.errors.push(json!({
+            "path": path.to_string_lossy(),
+            "error": err.to_string(),
+        }));
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "schema_version": SCHEMA_VERSION,
+            "directories": self.directories,
+            "entries": self.entries,
+            "errors": self.errors,
+        })
+    }
+}
+
+fn lock_document() -> std::sync::MutexGuard<'static, Document> {
+    DOCUMENT
+        .lock()
+        .expect("failed to lock mutex on JSON document")
+}
+
+// add the listing of a directory to the document
+pub fn add_directory(path: &Path, entries: &[&Entry], with_summary: bool) {
+    lock_document().add_directory(path, entries, with_summary);
+}
+
+// add (already sorted) entries that are not the listing of a directory
+pub fn add_entries(entries: &[&Entry]) {
+    lock_document()
+        .entries
+        .extend(entries.iter().map(|x| entry_to_json(x)));
+}
+
+// add an entry that was already converted, like by --stat
+pub fn add_value(value: Value) {
+    lock_document().entries.push(value);
+}
+
+pub fn add_error(path: &Path, err: &dyn Display) {
+    lock_document().add_error(path, err);
+}
+
+pub fn print_document() {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    // an error means that the output was closed
+    let _ = write_document(&mut out).and_then(|_| out.flush());
+}
+
+// write the document, ending in a single newline
+pub fn write_document(out: &mut impl Write) -> io::Result<()> {
+    let doc = lock_document().to_json();
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&doc).expect("failed to serialize JSON document")
+    )
+}
+
+fn entry_to_json(entry: &Entry) -> Value {
+    let filetype = metadata_filetype(&entry.metadata);
+
+    let mut obj = json!({
+        "name": entry.name.to_string_lossy(),
+        "path": entry.path().to_string_lossy(),
+        "type": filetype_name(filetype),
+        "is_dir": entry.metadata.is_dir(),
+        "is_symlink": entry.metadata.is_symlink(),
+        "size": entry.size(),
+        "mtime": entry.mtime().to_rfc3339(),
+        "link_dest": entry.link_dest.as_ref().map(|x| x.to_string_lossy()),
+    });
+
+    // with --dir-size
+    if let Some(result) = &entry.recursive_size {
+        match result {
+            Ok(size) => obj["recursive_size"] = json!(size),
+            Err(err) => {
+                obj["recursive_size"] = Value::Null;
+                obj["error"] = json!(err);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let perms = entry.metadata.permissions();
+        obj["mode"] = json!(perms.mode() & 0o7777);
+        obj["permissions"] = json!(crate::format_permissions(&perms));
+    }
+    obj
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Dereference;
+    use crate::testutil::TempDir;
+    use crate::{filter_hidden, list_dir, sort_entries, sum_subdir_sizes, Settings};
+
+    #[test]
+    fn recursive_size_of_known_tree() {
+        let tmp = TempDir::new();
+        tmp.file("sub/a", 100);
+        tmp.file("sub/deeper/b", 50);
+        tmp.file("top", 7);
+        let settings = Settings {
+            dir_size: true,
+            ..Settings::new()
+        };
+
+        let (mut entries, _) = list_dir(tmp.path(), &settings).unwrap();
+        sum_subdir_sizes(&mut entries, &settings);
+
+        let sub = entries.iter().find(|x| x.name == "sub").unwrap();
+        let value = entry_to_json(sub);
+        assert_eq!(value["recursive_size"], json!(150));
+        assert!(value.get("error").is_none());
+
+        // files have no recursive size
+        let top = entries.iter().find(|x| x.name == "top").unwrap();
+        assert!(entry_to_json(top).get("recursive_size").is_none());
+    }
+
+    #[test]
+    fn recursive_size_error() {
+        let tmp = TempDir::new();
+        let mut entry = Entry::from_path(&tmp.dir("sub"), Dereference::Never).unwrap();
+        entry.recursive_size = Some(Err("Permission denied".to_string()));
+
+        let value = entry_to_json(&entry);
+        assert_eq!(value["recursive_size"], Value::Null);
+        assert_eq!(value["error"], json!("Permission denied"));
+    }
+
+    // Returns entries of directory, the way they are shown
+    fn shown_entries(path: &Path, settings: &Settings) -> Vec<Entry> {
+        let (mut entries, _) = list_dir(path, settings).unwrap();
+        entries.retain(|x| filter_hidden(x, settings));
+        sort_entries(&mut entries, settings);
+        entries
+    }
+
+    #[test]
+    fn directories_are_keyed_by_path() {
+        let one = TempDir::new();
+        one.file("a.txt", 1);
+        let two = TempDir::new();
+        two.file("b.txt", 1);
+        two.file("c.txt", 1);
+        let settings = Settings::new();
+
+        let mut doc = Document::default();
+        for tmp in [&one, &two] {
+            let entries = shown_entries(tmp.path(), &settings);
+            doc.add_directory(tmp.path(), &entries.iter().collect::<Vec<_>>(), false);
+        }
+        let value = doc.to_json();
+        assert_eq!(value["schema_version"], json!(2));
+
+        let names = |tmp: &TempDir| {
+            value["directories"][&*tmp.path().to_string_lossy()]["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x["name"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names(&one), ["a.txt"]);
+        assert_eq!(names(&two), ["b.txt", "c.txt"]);
+
+        // the key tells where an entry is; without --summary, there are no totals
+        let listing = &value["directories"][&*one.path().to_string_lossy()];
+        assert!(listing["entries"][0].get("dir").is_none());
+        assert!(listing.get("entry_count").is_none());
+        assert!(listing.get("total_apparent_bytes").is_none());
+        assert_eq!(value["entries"], json!([]));
+    }
+
+    #[test]
+    fn totals_match_entries() {
+        let tmp = TempDir::new();
+        tmp.file("a", 100);
+        tmp.file("b", 50);
+        tmp.file("c", 7);
+        tmp.file("sub/d", 1000);
+        tmp.file(".hidden", 2000);
+
+        let totals = |settings: &Settings| {
+            let entries = shown_entries(tmp.path(), settings);
+            let mut doc = Document::default();
+            doc.add_directory(tmp.path(), &entries.iter().collect::<Vec<_>>(), true);
+            let value = doc.to_json();
+            let listing = value["directories"][&*tmp.path().to_string_lossy()].clone();
+
+            // only regular files add to the total
+            let array = listing["entries"].as_array().unwrap();
+            let sum = array
+                .iter()
+                .filter(|x| x["type"] == json!("file"))
+                .map(|x| x["size"].as_u64().unwrap())
+                .sum::<u64>();
+            assert_eq!(listing["total_apparent_bytes"], json!(sum));
+            assert_eq!(listing["entry_count"], json!(array.len()));
+            (
+                listing["entry_count"].as_u64().unwrap(),
+                listing["total_apparent_bytes"].as_u64().unwrap(),
+            )
+        };
+        assert_eq!(totals(&Settings::new()), (4, 157));
+        // hidden entries only count when they are shown
+        let all = Settings {
+            all: true,
+            ..Settings::new()
+        };
+        assert_eq!(totals(&all), (5, 2157));
+    }
+
+    #[test]
+    fn directory_listed_twice() {
+        let tmp = TempDir::new();
+        tmp.file("a", 10);
+        let settings = Settings::new();
+        let entries = shown_entries(tmp.path(), &settings);
+        let entries = entries.iter().collect::<Vec<_>>();
+
+        let mut doc = Document::default();
+        doc.add_directory(tmp.path(), &entries, true);
+        doc.add_directory(tmp.path(), &entries, true);
+        let value = doc.to_json();
+        let listing = &value["directories"][&*tmp.path().to_string_lossy()];
+        assert_eq!(listing["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(listing["entry_count"], json!(2));
+        assert_eq!(listing["total_apparent_bytes"], json!(20));
+    }
+
+    #[test]
+    fn total_is_allowed_with_json() {
+        let matches = crate::build_cli()
+            .try_get_matches_from(["dir", "--json", "--total"])
+            .unwrap();
+        assert!(matches.get_flag("summary"));
+    }
+}
+
+// EOB
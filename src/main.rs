@@ -3,40 +3,134 @@
 //  main.rs
 //
 
+pub mod background;
+pub mod cache;
+pub mod compare;
+pub mod dired;
 pub mod entry;
-
+pub mod json;
+pub mod perm;
+pub mod porcelain;
+pub mod progress;
+pub mod stat;
+pub mod stats;
+pub mod tree;
+pub mod watch;
+
+#[cfg(test)]
+mod testutil;
+
+use background::Background;
 use chrono::{DateTime, Datelike, Local};
-use clap::{Arg, ArgAction, ColorChoice, Command};
-use entry::Entry;
+use clap::{Arg, ArgAction, ArgMatches, ColorChoice, Command};
+use entry::{Dereference, Entry, TimeSource};
 use lazy_static::lazy_static;
 use once_cell::sync::OnceCell;
 #[cfg(unix)]
 use std::fs::Permissions;
-#[cfg(unix)]
 use std::sync::Mutex;
 use std::{
     cmp::Ordering,
     collections::HashMap,
     ffi::OsStr,
     fs::{self, File, Metadata},
-    io::{self, BufReader},
+    io::{self, BufReader, IsTerminal, Read, Write},
     path::{Path, PathBuf},
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 struct Settings {
     color: bool,
     bold: bool,
     all: bool,
+    hidden_only: bool,
     classify: bool,
     long: bool,
+    layout: Layout,
     one: bool,
-    sort_by_size: bool,
-    sort_by_time: bool,
-    sort_by_extension: bool,
+    headers: bool,
+    dir_headers: bool,
+    dir_count: bool,
+    dir_size: bool,
+    full_path: bool,
+    relative: bool,
+    canonical: bool,
+    max_open: Option<usize>,
+    escape_nonprintable: bool,
+    header_style: HeaderStyle,
+    width: Option<usize>,
+    column_separator: Option<String>,
+    row_group: Option<usize>,
+    indent: usize,
+    si: bool,
+    // with POSIXLY_CORRECT, totals count blocks of this size
+    block_size: Option<u64>,
+    align_sizes: bool,
+    compact: bool,
+    author: bool,
+    no_group: bool,
+    show_columns: Option<Vec<Column>>,
+    sparse: bool,
+    mime: bool,
+    full_time: bool,
+    recent_days: i64,
+    time_source: TimeSource,
+    time_precision: usize,
+    json: bool,
+    recursive: bool,
+    progress: bool,
+    dirs_tree: bool,
+    tree: bool,
+    depth: Option<usize>,
+    cache: bool,
+    merge: bool,
+    ignore_vanished: bool,
+    exec_by_shebang: bool,
+    exec_by_current_user: bool,
+    type_fallback: bool,
+    print0: bool,
+    porcelain: bool,
+    dired: bool,
+    newer_than: Option<DateTime<Local>>,
+    owner_filter: Option<u32>,
+    group_filter: Option<u32>,
+    perm_filter: Option<perm::PermFilter>,
+    older_than: Option<DateTime<Local>>,
+    exclude_dirs: Vec<glob::Pattern>,
+    match_patterns: Vec<glob::Pattern>,
+    ignore_patterns: Vec<glob::Pattern>,
+    ignore_case: bool,
+    c_collation: bool,
+    group_directories_first: bool,
+    dereference: Dereference,
+    deref_type: bool,
+    deref_size: bool,
+    deref_time: bool,
+    sort_keys: Vec<SortKey>,
     sort_reverse: bool,
+    sort_none: bool,
+    symlinks_as_target_type: bool,
+    preserve_arg_order: bool,
     color_by_extension: HashMap<String, u32>,
+    color_by_pattern: Vec<(glob::Pattern, u32)>,
+    color_by_filename: HashMap<String, u32>,
+    color_by_age: Vec<(std::time::Duration, u32)>,
+    color_by_age_mode: bool,
+    hot_seconds: Option<std::time::Duration>,
+    legend: bool,
+    legend_stderr: bool,
+    summary: bool,
+    show_hidden_count: bool,
+    color_scale: bool,
+    size_scale: Vec<(u64, u32)>,
     color_by_filetype: Vec<u32>,
     color_by_mode: Vec<u32>,
+    classify_by_filetype: Vec<bool>,
+    classify_by_mode: Vec<bool>,
+    indicator_by_filetype: Vec<String>,
+    indicator_exec: String,
+    size_field_by_filetype: Vec<SizeField>,
 }
 
 impl Settings {
@@ -52,17 +146,94 @@ impl Default for Settings {
             color: true,
             bold: true,
             all: false,
+            hidden_only: false,
             classify: true,
             long: true,
+            layout: Layout::Auto,
             one: false,
-            sort_by_size: false,
-            sort_by_time: false,
-            sort_by_extension: false,
+            headers: false,
+            dir_headers: true,
+            dir_count: false,
+            dir_size: false,
+            full_path: false,
+            relative: false,
+            canonical: false,
+            max_open: None,
+            // protect the terminal against filenames with escape sequences
+            escape_nonprintable: io::stdout().is_terminal(),
+            header_style: HeaderStyle::Slash,
+            width: None,
+            column_separator: None,
+            row_group: None,
+            indent: 0,
+            si: false,
+            block_size: None,
+            align_sizes: false,
+            compact: false,
+            author: false,
+            no_group: false,
+            show_columns: None,
+            sparse: false,
+            mime: false,
+            full_time: false,
+            recent_days: 90,
+            time_source: TimeSource::Modified,
+            time_precision: 9,
+            json: false,
+            recursive: false,
+            progress: false,
+            dirs_tree: false,
+            tree: false,
+            depth: None,
+            cache: false,
+            merge: false,
+            ignore_vanished: false,
+            exec_by_shebang: false,
+            exec_by_current_user: false,
+            type_fallback: true,
+            print0: false,
+            porcelain: false,
+            dired: false,
+            newer_than: None,
+            owner_filter: None,
+            group_filter: None,
+            perm_filter: None,
+            older_than: None,
+            exclude_dirs: Vec::new(),
+            match_patterns: Vec::new(),
+            ignore_patterns: Vec::new(),
+            ignore_case: false,
+            c_collation: false,
+            group_directories_first: true,
+            dereference: Dereference::Never,
+            deref_type: true,
+            deref_size: true,
+            deref_time: true,
+            sort_keys: Vec::new(),
             sort_reverse: false,
+            sort_none: false,
+            symlinks_as_target_type: false,
+            preserve_arg_order: false,
             color_by_extension: HashMap::new(),
+            color_by_pattern: Vec::new(),
+            color_by_filename: HashMap::new(),
+            color_by_age: Vec::new(),
+            color_by_age_mode: false,
+            hot_seconds: None,
+            legend: false,
+            legend_stderr: false,
+            summary: false,
+            show_hidden_count: false,
+            color_scale: false,
+            size_scale: default_size_scale(),
             // note, color zero is 'normal'
             color_by_filetype: vec![0; FT_MAX],
             color_by_mode: vec![0; FM_MAX],
+            classify_by_filetype: vec![true; FT_MAX],
+            classify_by_mode: vec![true; FM_MAX],
+            indicator_by_filetype: default_indicators(),
+            indicator_exec: "*".to_string(),
+            size_field_by_filetype: default_size_fields(),
         }
     }
 }
@@ -77,6 +248,106 @@ const FT_BLOCKDEV: usize = 5;
 const FT_CHARDEV: usize = 6;
 const FT_MAX: usize = 7;
 
+// what to show in the size column of the long listing
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SizeField {
+    Size,
+    // like <DIR>
+    Placeholder,
+    // major, minor device number
+    Device,
+    // number of hard links
+    Links,
+    Blank,
+}
+
+// the listing mode when no -l, -w or -1 is given
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    // wide on a terminal, long when the output is redirected
+    Auto,
+    Long,
+    Wide,
+}
+
+impl Layout {
+    // Returns true if the listing is long
+    // The terminal check is passed in, so that it can be tested
+    fn is_long(&self, is_terminal: impl FnOnce() -> bool) -> bool {
+        match self {
+            // on a terminal, names in columns are easiest to take in
+            // when redirected, the details are probably wanted
+            Layout::Auto => !is_terminal(),
+            Layout::Long => true,
+            Layout::Wide => false,
+        }
+    }
+}
+
+// what to sort by; entries that are equal are sorted by name
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Time,
+    Extension,
+    Type,
+}
+
+impl SortKey {
+    // Returns sort key by name, as given on the command-line
+    fn from_name(name: &str) -> Option<SortKey> {
+        match name {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "time" => Some(SortKey::Time),
+            "extension" => Some(SortKey::Extension),
+            "type" => Some(SortKey::Type),
+            _ => None,
+        }
+    }
+}
+
+// how directory names are shown above their listing
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HeaderStyle {
+    // with a trailing slash, like "src/"
+    Slash,
+    // exactly as given ("plain")
+    Raw,
+    // like `ls`; "src:"
+    Colon,
+}
+
+// Returns what is shown in the size column, per FT_xxx filetype
+fn default_size_fields() -> Vec<SizeField> {
+    let mut fields = vec![SizeField::Size; FT_MAX];
+    fields[FT_DIR] = SizeField::Placeholder;
+    // the size of special files is meaningless
+    fields[FT_FIFO] = SizeField::Blank;
+    fields[FT_SOCK] = SizeField::Blank;
+    fields[FT_BLOCKDEV] = SizeField::Device;
+    fields[FT_CHARDEV] = SizeField::Device;
+    fields
+}
+
+// Returns size scale: up to what size a file gets what color
+// The scale goes from green (small) through yellow to red (large)
+fn default_size_scale() -> Vec<(u64, u32)> {
+    const MIB: u64 = 1024 * 1024;
+    vec![(MIB, 32), (100 * MIB, 33), (u64::MAX, 31)]
+}
+
+// Returns classify indicators, per FT_xxx filetype
+fn default_indicators() -> Vec<String> {
+    let mut indicators = vec![String::new(); FT_MAX];
+    indicators[FT_DIR] = std::path::MAIN_SEPARATOR.to_string();
+    indicators[FT_SYMLINK] = "@".to_string();
+    indicators[FT_FIFO] = "|".to_string();
+    indicators[FT_SOCK] = "=".to_string();
+    indicators
+}
+
 // file mode constant indices into COLOR_BY_MODE
 const FM_EXEC: usize = 0;
 const FM_SUID: usize = 1;
@@ -87,18 +358,30 @@ const FM_MAX: usize = 4;
 // format time as short month name + day + hours + minutes if it is in the current year
 // or less than 90 days ago
 // Otherwise, format as short month name + day + year (omitting the time)
-fn format_time(dt: &DateTime<Local>) -> String {
-    let year = dt.year();
-
+fn format_time(dt: &DateTime<Local>, settings: &Settings) -> String {
     static NOW: OnceCell<DateTime<Local>> = OnceCell::new();
-    let now = NOW.get_or_init(|| chrono::Local::now());
+    let now = NOW.get_or_init(chrono::Local::now);
+    format_time_at(dt, now, settings)
+}
+
+// format time like format_time(), as seen at time "now"
+fn format_time_at(dt: &DateTime<Local>, now: &DateTime<Local>, settings: &Settings) -> String {
+    if settings.full_time {
+        return format_full_time(dt, settings.time_precision);
+    }
+
+    let year = dt.year();
     let current_year = now.year();
 
     if year == current_year {
         format!("{}", dt.format("%b %d %H:%M"))
+    } else if dt > now {
+        // a file from a future year is suspicious (bad clock?); always show the year
+        format!("{}", dt.format("%b %d  %Y"))
     } else {
+        // files from last year that are recent still show the time of day
         let days_since = dt.signed_duration_since(now).num_days();
-        if days_since >= -90 {
+        if days_since >= -settings.recent_days {
             format!("{}", dt.format("%b %d %H:%M"))
         } else {
             format!("{}", dt.format("%b %d  %Y"))
@@ -106,29 +389,85 @@ fn format_time(dt: &DateTime<Local>) -> String {
     }
 }
 
-fn format_size(size: u64) -> String {
-    if size < 900 {
+// format time as full date and time, with given number of fractional digits
+// for the seconds, and the timezone
+fn format_full_time(dt: &DateTime<Local>, precision: usize) -> String {
+    let mut buf = format!("{}", dt.format("%Y-%m-%d %H:%M:%S"));
+    if precision > 0 {
+        let nanos = format!("{:09}", dt.timestamp_subsec_nanos());
+        buf.push('.');
+        buf.push_str(&nanos[..precision]);
+    }
+    buf.push_str(&format!("{}", dt.format(" %z")));
+    buf
+}
+
+// format size in human readable units
+// SI units are powers of 1000 (kB, MB, GB), binary units are powers of 1024 (KiB, MiB, GiB)
+// The unit changes when the number reaches the base, so that sizes
+// never show as "0.9 kB" nor as "1000.0 kB"
+fn format_size(size: u64, si: bool) -> String {
+    const SI_UNITS: [&str; 8] = ["kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+    const IEC_UNITS: [&str; 8] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB"];
+
+    let (units, base) = if si {
+        (&SI_UNITS, 1000u64)
+    } else {
+        (&IEC_UNITS, 1024u64)
+    };
+
+    if size < base {
         return format!("{}", size);
     }
 
-    const UNITS: [char; 8] = ['k', 'M', 'G', 'T', 'P', 'E', 'Z', 'Y'];
+    let multiplier = base as f64;
+    let mut f = size as f64 / multiplier;
+    let mut unit_idx = 0;
+
+    // mind that the number is shown rounded to one decimal
+    while (f * 10.0).round() / 10.0 >= multiplier && unit_idx < units.len() - 1 {
+        f /= multiplier;
+        unit_idx += 1;
+    }
+
+    format!("{:.1} {}", f, units[unit_idx])
+}
 
-    const MULTIPLIER: f32 = 1000.0;
-    let mut f = size as f32 / MULTIPLIER;
+// Returns size like format_size(), but always of the same width
+// The number is right-justified, and the unit is padded; bytes are shown as "B"
+// so that the units line up in a column, like " 842 B  " and "18.0 KiB"
+fn format_size_fixed(size: u64, si: bool) -> String {
+    let formatted = format_size(size, si);
+    let (number, unit) = formatted.split_once(' ').unwrap_or((&formatted, "B"));
+    format_fixed_width(number, unit, si)
+}
 
-    let mut unit = UNITS[0];
-    for unit_idx in UNITS.iter() {
-        unit = *unit_idx;
+// Returns number and unit padded like format_size_fixed() does
+fn format_fixed_width(number: &str, unit: &str, si: bool) -> String {
+    // "1023.9" is the widest number
+    const NUMBER_WIDTH: usize = 6;
+    let unit_width = if si { 2 } else { 3 };
 
-        if f < 900.0 {
-            break;
-        }
+    format!("{:>NUMBER_WIDTH$} {:<unit_width$}", number, unit)
+}
 
-        f /= MULTIPLIER;
+// Returns placeholder for the size column, like "<DIR>"
+// With --align-sizes it lines up with the numbers, and the unit is left blank
+fn format_size_placeholder(placeholder: &str, settings: &Settings) -> String {
+    if settings.align_sizes {
+        format_fixed_width(placeholder, "", settings.si)
+    } else {
+        format!("{:^8}", placeholder)
     }
+}
 
-    let s = format!("{:.1} {}B", f, unit);
-    s
+// Returns size for the size column
+fn format_size_column(size: u64, settings: &Settings) -> String {
+    if settings.align_sizes {
+        format_size_fixed(size, settings.si)
+    } else {
+        format_size(size, settings.si)
+    }
 }
 
 #[cfg(windows)]
@@ -169,7 +508,7 @@ fn format_attributes(metadata: &Metadata) -> String {
 fn format_permissions(perms: &Permissions) -> String {
     use std::os::unix::fs::PermissionsExt;
 
-    let mode = perms.mode() as u32;
+    let mode = perms.mode();
 
     lazy_static! {
         static ref CACHE: Mutex<HashMap<u32, String>> = Mutex::new(HashMap::new());
@@ -179,12 +518,14 @@ fn format_permissions(perms: &Permissions) -> String {
         .expect("failed to lock mutex on internal cache memory");
 
     if let Some(mode_string) = cache.get(&mode) {
+        stats::cache_hit(stats::Cache::Permissions);
         // cache hit
         // NOTE we have to clone because can not return from local variable hashmap ...
         // (even though it's a static, yeah)
         return mode_string.clone();
     }
 
+    stats::cache_miss(stats::Cache::Permissions);
     let mut s = String::with_capacity(10);
 
     // filetype bit
@@ -274,12 +615,198 @@ fn format_permissions(perms: &Permissions) -> String {
     s
 }
 
+// Returns user name for uid, or the number if the user is unknown
+#[cfg(unix)]
+fn format_owner(uid: u32) -> String {
+    lazy_static! {
+        static ref CACHE: Mutex<HashMap<u32, String>> = Mutex::new(HashMap::new());
+    }
+    let mut cache = CACHE
+        .lock()
+        .expect("failed to lock mutex on internal cache memory");
+
+    if let Some(name) = cache.get(&uid) {
+        stats::cache_hit(stats::Cache::Owner);
+        return name.clone();
+    }
+    stats::cache_miss(stats::Cache::Owner);
+
+    let name = lookup_user_name(uid).unwrap_or_else(|| uid.to_string());
+    cache.insert(uid, name.clone());
+    name
+}
+
+#[cfg(unix)]
+fn lookup_user_name(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let mut pwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+        // SAFETY: all pointers point at valid memory of the given size
+        let err = unsafe {
+            libc::getpwuid_r(
+                uid as libc::uid_t,
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if err == libc::ERANGE && buf.len() < 1024 * 1024 {
+            // buffer too small; try again
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if err != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: on success, pw_name points at a C string inside buf
+        let name = unsafe { std::ffi::CStr::from_ptr((*result).pw_name) };
+        return Some(name.to_string_lossy().to_string());
+    }
+}
+
+// Returns group name for gid, or the number if the group is unknown
+#[cfg(unix)]
+fn format_group(gid: u32) -> String {
+    lazy_static! {
+        static ref CACHE: Mutex<HashMap<u32, String>> = Mutex::new(HashMap::new());
+    }
+    let mut cache = CACHE
+        .lock()
+        .expect("failed to lock mutex on internal cache memory");
+
+    if let Some(name) = cache.get(&gid) {
+        stats::cache_hit(stats::Cache::Group);
+        return name.clone();
+    }
+    stats::cache_miss(stats::Cache::Group);
+
+    let name = lookup_group_name(gid).unwrap_or_else(|| gid.to_string());
+    cache.insert(gid, name.clone());
+    name
+}
+
+#[cfg(unix)]
+fn lookup_group_name(gid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let mut grp = std::mem::MaybeUninit::<libc::group>::uninit();
+        let mut result: *mut libc::group = std::ptr::null_mut();
+
+        // SAFETY: all pointers point at valid memory of the given size
+        let err = unsafe {
+            libc::getgrgid_r(
+                gid as libc::gid_t,
+                grp.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if err == libc::ERANGE && buf.len() < 1024 * 1024 {
+            // buffer too small; try again
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if err != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: on success, gr_name points at a C string inside buf
+        let name = unsafe { std::ffi::CStr::from_ptr((*result).gr_name) };
+        return Some(name.to_string_lossy().to_string());
+    }
+}
+
+// Returns uid of user name
+#[cfg(unix)]
+fn lookup_uid(name: &str) -> Option<u32> {
+    let c_name = std::ffi::CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let mut pwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+        // SAFETY: all pointers point at valid memory of the given size
+        let err = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if err == libc::ERANGE && buf.len() < 1024 * 1024 {
+            // buffer too small; try again
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if err != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: on success, result points at pwd
+        return Some(unsafe { (*result).pw_uid } as u32);
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_uid(_name: &str) -> Option<u32> {
+    None
+}
+
+// Returns gid of group name
+#[cfg(unix)]
+fn lookup_gid(name: &str) -> Option<u32> {
+    let c_name = std::ffi::CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let mut grp = std::mem::MaybeUninit::<libc::group>::uninit();
+        let mut result: *mut libc::group = std::ptr::null_mut();
+
+        // SAFETY: all pointers point at valid memory of the given size
+        let err = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                grp.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if err == libc::ERANGE && buf.len() < 1024 * 1024 {
+            // buffer too small; try again
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if err != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: on success, result points at grp
+        return Some(unsafe { (*result).gr_gid } as u32);
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_gid(_name: &str) -> Option<u32> {
+    None
+}
+
 // Returns FT_xxx constant for entry filetype
 #[cfg(unix)]
 fn metadata_filetype(metadata: &Metadata) -> usize {
     use std::os::unix::fs::PermissionsExt;
 
-    let mode = metadata.permissions().mode() as u32;
+    let mode = metadata.permissions().mode();
     match mode & entry::S_IFMT {
         entry::S_IFREG => FT_FILE,
         entry::S_IFDIR => FT_DIR,
@@ -320,11 +847,50 @@ fn format_color(color: u32, config_bold: bool) -> Option<String> {
     }
 }
 
+// Returns color for the size column with --color-scale
+// Only regular files have a size that is worth coloring
+fn size_scale_color(entry: &Entry, column: Column, settings: &Settings) -> Option<String> {
+    if !settings.color || !settings.color_scale || column != Column::Size {
+        return None;
+    }
+    if !entry.metadata.is_file() {
+        return None;
+    }
+    if settings.size_field_by_filetype[FT_FILE] != SizeField::Size {
+        return None;
+    }
+
+    let size = entry.size();
+    let (_, color) = settings
+        .size_scale
+        .iter()
+        .find(|(max_size, _)| size <= *max_size)?;
+    format_color(*color, settings.bold)
+}
+
 fn colorize(entry: &Entry, settings: &Settings) -> Option<String> {
     if !settings.color {
         return None;
     }
 
+    // with --hot-seconds, very recently modified files are the youngest age bucket
+    // They are shown bold and underlined, on top of their usual color
+    if let Some(hot_seconds) = settings.hot_seconds {
+        if file_age(entry, settings) <= hot_seconds {
+            let color = colorize_entry(entry, settings).unwrap_or_default();
+            return Some(format!("\x1b[1;4m{}", color));
+        }
+    }
+    colorize_entry(entry, settings)
+}
+
+fn colorize_entry(entry: &Entry, settings: &Settings) -> Option<String> {
+    if settings.color_by_age_mode {
+        if let Some(color) = color_by_age(entry, settings) {
+            return format_color(color, settings.bold);
+        }
+    }
+
     let filetype = metadata_filetype(&entry.metadata);
 
     if filetype == FT_DIR {
@@ -362,16 +928,34 @@ fn colorize(entry: &Entry, settings: &Settings) -> Option<String> {
             return format_color(color, settings.bold);
         }
 
+        // by exact filename
+        if let Some(color) = settings
+            .color_by_filename
+            .get(&*entry.name.to_string_lossy())
+        {
+            return format_color(*color, settings.bold);
+        }
+
         // by filename extension
         if let Some(color) = color_by_ext(&entry.name, settings) {
             return format_color(color, settings.bold);
         }
 
-        if entry.is_exec() {
+        // by filename pattern
+        if let Some(color) = color_by_pattern(&entry.name, settings) {
+            return format_color(color, settings.bold);
+        }
+
+        if is_exec_colored(entry, settings) {
             let colormap = &settings.color_by_mode;
             let color = colormap[FM_EXEC];
             return format_color(color, settings.bold);
         }
+
+        // without the fallback, plain files are not colored at all
+        if !settings.type_fallback {
+            return None;
+        }
     }
 
     let colormap = &settings.color_by_filetype;
@@ -379,6 +963,26 @@ fn colorize(entry: &Entry, settings: &Settings) -> Option<String> {
     format_color(color, settings.bold)
 }
 
+// Returns color of the first age bucket that the entry fits in
+fn color_by_age(entry: &Entry, settings: &Settings) -> Option<u32> {
+    let age = file_age(entry, settings);
+    settings
+        .color_by_age
+        .iter()
+        .find(|(max_age, _)| age <= *max_age)
+        .map(|(_, color)| *color)
+}
+
+// Returns how long ago the entry was modified
+// With --accessed or --created, it is the age of that time instead
+// Files from the future have age zero
+fn file_age(entry: &Entry, settings: &Settings) -> std::time::Duration {
+    Local::now()
+        .signed_duration_since(entry.time(settings.time_source))
+        .to_std()
+        .unwrap_or_default()
+}
+
 // Returns color code for file extension, if the file extension is known
 fn color_by_ext(filename: &OsStr, settings: &Settings) -> Option<u32> {
     let ext = get_filename_ext(filename)?.to_lowercase();
@@ -387,6 +991,22 @@ fn color_by_ext(filename: &OsStr, settings: &Settings) -> Option<u32> {
     Some(*color)
 }
 
+// Returns color code for the first pattern that matches the filename
+// Patterns are only tried when there is no exact extension match
+fn color_by_pattern(filename: &OsStr, settings: &Settings) -> Option<u32> {
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..glob::MatchOptions::new()
+    };
+    let lossy_name = filename.to_string_lossy();
+
+    settings
+        .color_by_pattern
+        .iter()
+        .find(|(pattern, _)| pattern.matches_with(&lossy_name, options))
+        .map(|(_, color)| *color)
+}
+
 fn get_filename_ext(filename: &OsStr) -> Option<String> {
     let lossy_name = filename.to_string_lossy();
     let parts = lossy_name.split(".").collect::<Vec<&str>>();
@@ -398,146 +1018,772 @@ fn get_filename_ext(filename: &OsStr) -> Option<String> {
     }
 }
 
-fn format_entry(entry: &Entry, settings: &Settings) -> String {
-    if settings.one {
-        // show only the name
-        return entry.name.to_string_lossy().to_string();
+// columns in long listing mode (not including the filename, which is always last)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    Time,
+    Perms,
+    Author,
+    Group,
+    Size,
+    Sparse,
+    Mime,
+}
+
+impl Column {
+    // Returns title of the column, for the header line
+    fn title(&self) -> &'static str {
+        match self {
+            Column::Time => "Date",
+            #[cfg(windows)]
+            Column::Perms => "Attr",
+            #[cfg(not(windows))]
+            Column::Perms => "Perms",
+            Column::Author => "Author",
+            Column::Group => "Group",
+            Column::Size => "Size",
+            Column::Sparse => "Sparse",
+            Column::Mime => "Type",
+        }
     }
 
-    #[cfg(unix)]
-    let perms_str = format_permissions(&entry.metadata.permissions());
+    // Returns column by name, as given on the command-line
+    // The filename is not a column, and it yields None
+    fn from_name(name: &str) -> Option<Option<Column>> {
+        match name {
+            "date" | "time" => Some(Some(Column::Time)),
+            "perms" | "attr" => Some(Some(Column::Perms)),
+            "author" => Some(Some(Column::Author)),
+            "group" => Some(Some(Column::Group)),
+            "size" => Some(Some(Column::Size)),
+            "sparse" => Some(Some(Column::Sparse)),
+            "type" | "mime" => Some(Some(Column::Mime)),
+            "name" => Some(None),
+            _ => None,
+        }
+    }
 
-    let time_str = format_time(&entry.mtime());
+    fn min_width(&self) -> usize {
+        match self {
+            Column::Size => 8,
+            _ => 0,
+        }
+    }
 
-    let size_str;
-    if entry.metadata.is_dir() {
-        size_str = format!("{:^8}", "<DIR>");
-    } else {
-        size_str = format_size(entry.metadata.len());
+    fn align_right(&self) -> bool {
+        *self == Column::Size
     }
+}
 
-    let display_name = if let Some(color_str) = colorize(entry, settings) {
-        // format with colors
-        const END_COLOR: &'static str = "\x1b[0m";
-        format!(
-            "{}{}{}",
-            &color_str,
-            entry.name.to_string_lossy(),
-            END_COLOR
-        )
-    } else {
-        entry.name.to_string_lossy().to_string()
-    };
+// Returns the columns to show in long listing mode
+fn long_columns(settings: &Settings) -> Vec<Column> {
+    let mut columns = vec![Column::Time];
 
     #[cfg(unix)]
-    let mut buf = format!(
-        "{}  {}  {:>8}  {}",
-        &time_str, &perms_str, &size_str, &display_name
-    );
+    columns.push(Column::Perms);
+    // Windows attributes are only shown with --all
     #[cfg(windows)]
-    let mut buf = if settings.all {
-        format!(
-            "{}  {}  {:>8}  {}",
-            &time_str,
-            &format_attributes(&entry.metadata),
-            &size_str,
-            &display_name
-        )
-    } else {
-        format!("{}  {:>8}  {}", &time_str, &size_str, &display_name)
-    };
-    #[cfg(not(any(unix, windows)))]
-    let mut buf = format!("{}  {:>8}  {}", &time_str, &size_str, &display_name);
+    if settings.all {
+        columns.push(Column::Perms);
+    }
 
-    if let Some(token) = classify(entry, settings) {
-        buf.push(token);
+    // on most systems, the author is the same as the owner
+    if settings.author || show_column(Column::Author, settings) {
+        columns.push(Column::Author);
+    }
+    // the group goes with the author, like in `ls -l`
+    if (settings.author && !settings.no_group) || show_column(Column::Group, settings) {
+        columns.push(Column::Group);
     }
 
-    if entry.metadata.is_symlink() {
-        if let Some(linkdest_path) = &entry.link_dest {
-            let display_linkdest = linkdest_path.to_string_lossy();
-            buf.push_str(&format!(" -> {}", &display_linkdest));
+    columns.push(Column::Size);
+
+    if settings.sparse || show_column(Column::Sparse, settings) {
+        columns.push(Column::Sparse);
+    }
+    if settings.mime || show_column(Column::Mime, settings) {
+        columns.push(Column::Mime);
+    }
+
+    if let Some(show_columns) = &settings.show_columns {
+        columns.retain(|column| show_columns.contains(column));
+    }
+    if settings.compact {
+        columns.retain(|column| *column != Column::Time);
+    }
+    if settings.no_group {
+        columns.retain(|column| *column != Column::Group);
+    }
+    columns
+}
+
+// Returns true if the column was explicitly selected by --columns-show
+fn show_column(column: Column, settings: &Settings) -> bool {
+    match &settings.show_columns {
+        Some(show_columns) => show_columns.contains(&column),
+        None => false,
+    }
+}
+
+// Parse a comma-separated list of column names
+fn parse_columns(arg: &str) -> Result<Vec<Column>, String> {
+    let mut columns = Vec::new();
+
+    for name in arg.split(',') {
+        let name = name.trim();
+        match Column::from_name(name) {
+            Some(Some(column)) => {
+                if !columns.contains(&column) {
+                    columns.push(column);
+                }
+            }
+            // the filename is always shown
+            Some(None) => {}
+            None => return Err(format!("unknown column '{}'", name)),
         }
-        // else: should not / can not happen, just ignore it
     }
+    Ok(columns)
+}
 
-    buf
+fn format_column(entry: &Entry, column: Column, settings: &Settings) -> String {
+    match column {
+        Column::Time => format_time(&entry.time(settings.time_source), settings),
+        #[cfg(unix)]
+        Column::Perms => format_permissions(&entry.metadata.permissions()),
+        #[cfg(windows)]
+        Column::Perms => format_attributes(&entry.metadata),
+        #[cfg(not(any(unix, windows)))]
+        Column::Perms => String::new(),
+        #[cfg(unix)]
+        Column::Author => {
+            use std::os::unix::fs::MetadataExt;
+            format_owner(entry.metadata.uid())
+        }
+        #[cfg(not(unix))]
+        Column::Author => "-".to_string(),
+        #[cfg(unix)]
+        Column::Group => {
+            use std::os::unix::fs::MetadataExt;
+            format_group(entry.metadata.gid())
+        }
+        #[cfg(not(unix))]
+        Column::Group => "-".to_string(),
+        Column::Size => format_size_field(entry, settings),
+        Column::Sparse => format_sparse(entry),
+        Column::Mime => format_mime(entry),
+    }
 }
 
-fn format_wide_entry(entry: &Entry, settings: &Settings) -> String {
-    let mut buf = if let Some(color_str) = colorize(entry, settings) {
-        // format with colors
-        const END_COLOR: &'static str = "\x1b[0m";
-        format!(
-            "{}{}{}",
-            &color_str,
-            entry.name.to_string_lossy(),
-            END_COLOR
-        )
-    } else {
-        entry.name.to_string_lossy().to_string()
-    };
-    if let Some(token) = classify(entry, settings) {
-        buf.push(token);
+// Returns sparse marker and how much of the file is allocated
+fn format_sparse(entry: &Entry) -> String {
+    if !entry.is_sparse() {
+        return String::new();
+    }
+
+    match entry.allocated_size() {
+        Some(allocated) => {
+            let percentage = allocated * 100 / std::cmp::max(entry.size(), 1);
+            format!("S {:>3}%", percentage)
+        }
+        None => "S".to_string(),
     }
-    buf
 }
 
-fn classify(entry: &Entry, settings: &Settings) -> Option<char> {
-    if !settings.classify {
-        return None;
+// Returns content type of file, detected by looking at the first bytes
+fn format_mime(entry: &Entry) -> String {
+    // only look inside regular files
+    if !entry.metadata.is_file() {
+        return "-".to_string();
+    }
+
+    match infer::get_from_path(entry.path()) {
+        Ok(Some(kind)) => kind.mime_type().to_string(),
+        // unknown content, or we may not read the file
+        Ok(None) | Err(_) => "-".to_string(),
     }
+}
 
+// Returns contents of the size column
+fn format_size_field(entry: &Entry, settings: &Settings) -> String {
     let filetype = metadata_filetype(&entry.metadata);
 
-    match filetype {
-        FT_FILE => {
-            if entry.is_exec() {
-                Some('*')
+    // with --dir-size, directories show the total size of their contents
+    if let Some(result) = &entry.recursive_size {
+        return match result {
+            Ok(size) => format_size_column(*size, settings),
+            Err(_) => "?".to_string(),
+        };
+    }
+
+    match settings.size_field_by_filetype[filetype] {
+        SizeField::Size => format_size_column(entry.size(), settings),
+        SizeField::Placeholder => {
+            if let Some(count) = entry.dir_count {
+                format_size_placeholder(&format!("[{}]", count), settings)
             } else {
-                None
+                let placeholder = match filetype {
+                    FT_DIR => "<DIR>",
+                    FT_SYMLINK => "<LINK>",
+                    FT_FIFO => "<FIFO>",
+                    FT_SOCK => "<SOCK>",
+                    FT_BLOCKDEV => "<BLK>",
+                    FT_CHARDEV => "<CHR>",
+                    _ => "<FILE>",
+                };
+                format_size_placeholder(placeholder, settings)
             }
         }
-        FT_DIR => Some(std::path::MAIN_SEPARATOR),
-        FT_SYMLINK => {
-            if settings.long {
-                None
+        SizeField::Device => {
+            if let Some((major, minor)) = entry.device_number() {
+                format!("{}, {}", major, minor)
             } else {
-                Some('@')
+                String::new()
             }
         }
-        FT_FIFO => Some('|'),
-        FT_SOCK => Some('='),
-        _ => None,
+        SizeField::Links => format!("{}", entry.nlink()),
+        SizeField::Blank => String::new(),
     }
 }
 
-fn load_config() -> Settings {
-    if let Some(config_path) = dirs::config_dir() {
-        let mut config_file = PathBuf::from(config_path);
-        config_file.push("dir");
-        config_file.push("dir.json");
+// Returns the formatted fields of a long listing line
+// The fields are padded to the given column widths
+fn format_fields(fields: &[String], columns: &[Column], widths: &[usize]) -> String {
+    let mut buf = String::new();
 
-        if !config_file.exists() {
-            return Settings::default();
-        }
+    for ((field, column), width) in fields.iter().zip(columns.iter()).zip(widths.iter()) {
+        buf.push_str(&pad_field(field, *column, *width));
+        buf.push_str("  ");
+    }
+    buf
+}
 
-        let f = File::open(&config_file).expect(&format!(
-            "error: failed to open {}",
-            config_file.to_string_lossy()
-        ));
-        let reader = BufReader::new(f);
-        let data: serde_json::Value = serde_json::from_reader(reader).expect(&format!(
-            "error: {}: syntax error in JSON",
-            config_file.to_string_lossy()
-        ));
+// Returns field padded to column width
+fn pad_field(field: &str, column: Column, width: usize) -> String {
+    if column.align_right() {
+        format!("{:>width$}", field)
+    } else {
+        format!("{:<width$}", field)
+    }
+}
 
-        return load_config_data(&data, &config_file);
+// Returns vec of column widths for long listing mode
+fn determine_long_column_widths(
+    rows: &[Vec<String>],
+    columns: &[Column],
+    settings: &Settings,
+) -> Vec<usize> {
+    let mut widths = columns
+        .iter()
+        .map(|column| {
+            if settings.headers {
+                std::cmp::max(column.min_width(), column.title().chars().count())
+            } else {
+                column.min_width()
+            }
+        })
+        .collect::<Vec<usize>>();
+
+    for fields in rows.iter() {
+        for (width, field) in widths.iter_mut().zip(fields.iter()) {
+            *width = std::cmp::max(*width, field.chars().count());
+        }
     }
-    Settings::default()
+    widths
 }
 
-// Returns color code
+fn format_header(columns: &[Column], widths: &[usize]) -> String {
+    let titles = columns
+        .iter()
+        .map(|column| column.title().to_string())
+        .collect::<Vec<String>>();
+
+    let mut buf = format_fields(&titles, columns, widths);
+    buf.push_str("Name");
+    buf
+}
+
+fn format_entry(
+    entry: &Entry,
+    fields: &[String],
+    columns: &[Column],
+    widths: &[usize],
+    settings: &Settings,
+) -> String {
+    let display_name = if let Some(color_str) = colorize(entry, settings) {
+        // format with colors
+        const END_COLOR: &str = "\x1b[0m";
+        format!(
+            "{}{}{}",
+            &color_str,
+            display_name(entry, settings),
+            END_COLOR
+        )
+    } else {
+        display_name(entry, settings)
+    };
+
+    let mut buf = format_entry_fields(entry, fields, columns, widths, settings);
+    buf.push_str(&display_name);
+    buf.push_str(&format_entry_tail(entry, settings));
+    buf
+}
+
+// Returns the padded columns that go before the name
+fn format_entry_fields(
+    entry: &Entry,
+    fields: &[String],
+    columns: &[Column],
+    widths: &[usize],
+    settings: &Settings,
+) -> String {
+    let mut buf = String::new();
+    for ((field, column), width) in fields.iter().zip(columns.iter()).zip(widths.iter()) {
+        let padded = pad_field(field, *column, *width);
+
+        // color is applied after padding, so that it does not count for the width
+        match size_scale_color(entry, *column, settings) {
+            Some(color_str) => {
+                const END_COLOR: &str = "\x1b[0m";
+                let colored = format!("{}{}{}", &color_str, field, END_COLOR);
+                buf.push_str(&padded.replacen(field.as_str(), &colored, 1));
+            }
+            None => buf.push_str(&padded),
+        }
+        buf.push_str("  ");
+    }
+    buf
+}
+
+// Returns what goes after the name: classify indicator, link destination,
+// and canonical path
+fn format_entry_tail(entry: &Entry, settings: &Settings) -> String {
+    let mut buf = String::new();
+
+    if let Some(token) = classify(entry, settings) {
+        buf.push_str(token);
+    }
+
+    if entry.metadata.is_symlink() {
+        if let Some(linkdest_path) = &entry.link_dest {
+            let display_linkdest = format_link_dest(entry, linkdest_path, settings);
+            buf.push_str(&format!(" -> {}", &display_linkdest));
+        }
+        // else: should not / can not happen, just ignore it
+    }
+
+    // with --canonical, show where the entry really is
+    if settings.canonical {
+        let path = entry.path();
+        match fs::canonicalize(&path) {
+            Ok(canonical) => {
+                buf.push_str(&format!("  => {}", display_link_dest(&canonical, settings)));
+            }
+            // dangling link, or no permission to look
+            Err(_) => {
+                buf.push_str(&format!(
+                    "  => {} (unresolved)",
+                    display_link_dest(&path, settings)
+                ));
+            }
+        }
+    }
+
+    buf
+}
+
+fn format_wide_entry(entry: &Entry, settings: &Settings) -> String {
+    let mut buf = if let Some(color_str) = colorize(entry, settings) {
+        // format with colors
+        const END_COLOR: &str = "\x1b[0m";
+        format!(
+            "{}{}{}",
+            &color_str,
+            display_name(entry, settings),
+            END_COLOR
+        )
+    } else {
+        display_name(entry, settings)
+    };
+    if let Some(token) = classify(entry, settings) {
+        buf.push_str(token);
+    }
+    buf
+}
+
+// Returns the name of the entry as it is shown
+fn display_name(entry: &Entry, settings: &Settings) -> String {
+    let name = raw_display_name(entry, settings);
+    if settings.escape_nonprintable {
+        sanitize_name(&name)
+    } else {
+        name
+    }
+}
+
+// Returns the name of the entry as it is shown, but without escaping
+// This is for formats that do their own escaping
+fn raw_display_name(entry: &Entry, settings: &Settings) -> String {
+    if settings.full_path {
+        entry.path().to_string_lossy().to_string()
+    } else if settings.relative {
+        entry.relative_path().to_string_lossy().to_string()
+    } else {
+        entry.name.to_string_lossy().to_string()
+    }
+}
+
+// Returns symlink destination, colored like the file that it points at
+// so that a link to "settings.yaml" gets the color for YAML files
+// A dangling link has no color
+fn format_link_dest(entry: &Entry, link_dest: &Path, settings: &Settings) -> String {
+    let display_linkdest = display_link_dest(link_dest, settings);
+    if !settings.color {
+        return display_linkdest;
+    }
+
+    // a relative link is relative to the directory that the link is in
+    let target_path = entry.parent.join(link_dest);
+    let color_str = Entry::from_path(&target_path, Dereference::Follow)
+        .ok()
+        .and_then(|target| colorize(&target, settings));
+    match color_str {
+        Some(color_str) => {
+            const END_COLOR: &str = "\x1b[0m";
+            format!("{}{}{}", &color_str, display_linkdest, END_COLOR)
+        }
+        None => display_linkdest,
+    }
+}
+
+// Returns symlink destination as it is shown
+// It is escaped just like names are; a target may hold control characters too
+fn display_link_dest(link_dest: &Path, settings: &Settings) -> String {
+    let dest = link_dest.to_string_lossy();
+    if settings.escape_nonprintable {
+        sanitize_name(&dest)
+    } else {
+        dest.to_string()
+    }
+}
+
+// Returns name with control characters escaped
+// A filename may contain escape sequences that mess with the terminal;
+// only control characters are escaped, other UTF-8 is kept as is
+fn sanitize_name(name: &str) -> String {
+    if !name.chars().any(|c| c.is_control()) {
+        return name.to_string();
+    }
+
+    let mut buf = String::with_capacity(name.len() + 8);
+    for c in name.chars() {
+        match c {
+            '\t' => buf.push_str("\\t"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            // C0, DEL, and C1 control characters
+            _ if c.is_control() => buf.push_str(&format!("\\x{:02x}", c as u32)),
+            _ => buf.push(c),
+        }
+    }
+    buf
+}
+
+// Returns true if entry gets the color for executables
+// With "exec_by_current_user" in the config, files that have execute bits
+// but that we may not execute ourselves are not colored as executable
+fn is_exec_colored(entry: &Entry, settings: &Settings) -> bool {
+    if settings.exec_by_current_user && entry.is_exec() {
+        return entry.is_executable_by_current_user();
+    }
+    is_executable(entry, settings)
+}
+
+// Returns true if entry is an executable file
+// With --exec-by-shebang, scripts starting with "#!" are executable too
+fn is_executable(entry: &Entry, settings: &Settings) -> bool {
+    if entry.is_exec() {
+        return true;
+    }
+    settings.exec_by_shebang && entry.metadata.is_file() && has_shebang(entry)
+}
+
+// Returns true if the file starts with "#!"
+// Unreadable files are simply not scripts
+fn has_shebang(entry: &Entry) -> bool {
+    // a file that was rewritten has a different mtime or size
+    type ShebangKey = (PathBuf, Option<std::time::SystemTime>, u64);
+
+    lazy_static! {
+        // colorize and classify both ask; read the file only once
+        static ref CACHE: Mutex<HashMap<ShebangKey, bool>> = Mutex::new(HashMap::new());
+    }
+    let mut cache = CACHE
+        .lock()
+        .expect("failed to lock mutex on internal cache memory");
+
+    let path = entry.path();
+    let key = (path.clone(), entry.metadata.modified().ok(), entry.size());
+    if let Some(shebang) = cache.get(&key) {
+        return *shebang;
+    }
+
+    let mut buf = [0u8; 2];
+    let shebang = File::open(path)
+        .and_then(|mut f| f.read_exact(&mut buf))
+        .map(|_| &buf == b"#!")
+        .unwrap_or(false);
+
+    cache.insert(key, shebang);
+    shebang
+}
+
+// Returns classify indicator for entry, if any
+fn classify<'a>(entry: &Entry, settings: &'a Settings) -> Option<&'a str> {
+    if !settings.classify {
+        return None;
+    }
+
+    let filetype = metadata_filetype(&entry.metadata);
+    if filetype != FT_FILE && !settings.classify_by_filetype[filetype] {
+        return None;
+    }
+
+    let indicator = match filetype {
+        FT_FILE => {
+            if is_executable(entry, settings) && settings.classify_by_mode[FM_EXEC] {
+                &settings.indicator_exec
+            } else {
+                return None;
+            }
+        }
+        FT_SYMLINK => {
+            if settings.long {
+                return None;
+            }
+            &settings.indicator_by_filetype[FT_SYMLINK]
+        }
+        _ => &settings.indicator_by_filetype[filetype],
+    };
+
+    if indicator.is_empty() {
+        None
+    } else {
+        Some(indicator)
+    }
+}
+
+// Returns path of the config file
+// This is (in order) $DIR_CONFIG, $DIR_CONFIG_HOME/dir/dir.json,
+// or dir/dir.json in the platform's config directory
+fn config_file_path() -> Option<PathBuf> {
+    if let Some(config_file) = env_path("DIR_CONFIG") {
+        return Some(config_file);
+    }
+
+    let config_path = env_path("DIR_CONFIG_HOME").or_else(dirs::config_dir)?;
+    Some(config_path.join("dir").join("dir.json"))
+}
+
+// Returns path from environment variable, if set and not empty
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+}
+
+// load the config file
+// If verbose, report which config file is used
+// The terminal is asked for its background color only if may_query is set
+fn load_config(verbose: bool, background: Option<Background>, may_query: bool) -> Settings {
+    if let Some(config_file) = config_file_path() {
+        if !config_file.exists() {
+            if verbose {
+                eprintln!(
+                    "dir: config file: none ({} not found)",
+                    config_file.to_string_lossy()
+                );
+            }
+            // a config file that is explicitly given must exist
+            if env_path("DIR_CONFIG").is_some() {
+                eprintln!(
+                    "{}: config file not found (from $DIR_CONFIG)",
+                    config_file.to_string_lossy()
+                );
+                std::process::exit(2);
+            }
+            return default_settings();
+        }
+
+        let f = File::open(&config_file)
+            .unwrap_or_else(|_| panic!("error: failed to open {}", config_file.to_string_lossy()));
+        let reader = BufReader::new(f);
+        let mut data: serde_json::Value = serde_json::from_reader(reader).unwrap_or_else(|_| {
+            panic!(
+                "error: {}: syntax error in JSON",
+                config_file.to_string_lossy()
+            )
+        });
+
+        if verbose {
+            eprintln!("dir: config file: {}", config_file.to_string_lossy());
+        }
+        if apply_theme(&mut data, background, may_query, &config_file, verbose) > 0 {
+            std::process::exit(2);
+        }
+        return load_config_data(&data, &config_file);
+    }
+
+    // there is no config directory on this system
+    if verbose {
+        eprintln!("dir: config file: none (no config directory; set $DIR_CONFIG)");
+    }
+    default_settings()
+}
+
+// Returns the built-in defaults, with the colors from $LS_COLORS
+// The config file is loaded on top of this, so it takes precedence
+fn default_settings() -> Settings {
+    let mut settings = Settings::default();
+    if let Ok(ls_colors) = std::env::var("LS_COLORS") {
+        load_ls_colors(&ls_colors, &mut settings);
+    }
+    settings
+}
+
+// load colors from $LS_COLORS, like "di=01;34:ln=01;36:*.tar=01;31"
+// Only the keys that we have a setting for are used; others are ignored
+fn load_ls_colors(ls_colors: &str, settings: &mut Settings) {
+    for item in ls_colors.split(':') {
+        let Some((key, codes)) = item.split_once('=') else {
+            continue;
+        };
+        let Some(color) = parse_ls_color(codes) else {
+            continue;
+        };
+
+        if let Some(ext) = key.strip_prefix("*.") {
+            if !ext.is_empty() && !is_glob_pattern(ext) {
+                settings
+                    .color_by_extension
+                    .insert(ext.to_lowercase(), color);
+            }
+            continue;
+        }
+        match key {
+            "fi" => settings.color_by_filetype[FT_FILE] = color,
+            "di" => settings.color_by_filetype[FT_DIR] = color,
+            "ln" => settings.color_by_filetype[FT_SYMLINK] = color,
+            "pi" => settings.color_by_filetype[FT_FIFO] = color,
+            "so" => settings.color_by_filetype[FT_SOCK] = color,
+            "bd" => settings.color_by_filetype[FT_BLOCKDEV] = color,
+            "cd" => settings.color_by_filetype[FT_CHARDEV] = color,
+            "ex" => settings.color_by_mode[FM_EXEC] = color,
+            "su" => settings.color_by_mode[FM_SUID] = color,
+            "sg" => settings.color_by_mode[FM_SGID] = color,
+            "st" => settings.color_by_mode[FM_STICKY] = color,
+            _ => {}
+        }
+    }
+}
+
+// Returns color for SGR codes like "01;34"
+// Bold is left to the "bold" setting. There is only room for one color,
+// so 256 colors and true colors are not supported
+fn parse_ls_color(codes: &str) -> Option<u32> {
+    let mut color = None;
+    for code in codes.split(';') {
+        match code.parse::<u32>().ok()? {
+            0 => color = Some(0),
+            code @ (7 | 30..=37 | 40..=47 | 90..=97 | 100..=107) => color = Some(code),
+            38 | 48 => return None,
+            // other attributes, like bold and underline
+            _ => {}
+        }
+    }
+    color
+}
+
+// the "theme" in the config file has "light" and "dark" variants
+// The variant for the terminal background overrides the settings at top-level
+// Returns number of errors
+fn apply_theme(
+    data: &mut serde_json::Value,
+    background: Option<Background>,
+    may_query: bool,
+    config_file: &Path,
+    verbose: bool,
+) -> u32 {
+    let theme = match data.get("theme") {
+        Some(serde_json::Value::Object(theme)) => theme.clone(),
+        Some(_) => {
+            eprintln!(
+                "{}: 'theme' should be a map: {{\"light\": {{...}}, \"dark\": {{...}}}}",
+                config_file.to_string_lossy()
+            );
+            return 1;
+        }
+        None => return 0,
+    };
+
+    let mut errors = 0;
+    for key in theme.keys() {
+        if Background::from_name(key).is_none() {
+            eprintln!(
+                "{}: theme '{}' should be either 'light' or 'dark'",
+                config_file.to_string_lossy(),
+                key
+            );
+            errors += 1;
+        }
+    }
+    if errors > 0 {
+        return errors;
+    }
+
+    // only ask the terminal when colors are going to be shown at all
+    let color_off = data.get("color") == Some(&serde_json::Value::Bool(false));
+    let background = background
+        .or_else(|| {
+            if may_query && !color_off {
+                background::detect()
+            } else {
+                None
+            }
+        })
+        .unwrap_or(Background::Dark);
+    if verbose {
+        eprintln!("dir: theme: {}", background.name());
+    }
+
+    let variant = match theme.get(background.name()) {
+        Some(serde_json::Value::Object(variant)) => variant,
+        Some(_) => {
+            eprintln!(
+                "{}: theme '{}' should be a map of settings",
+                config_file.to_string_lossy(),
+                background.name()
+            );
+            return 1;
+        }
+        None => return 0,
+    };
+
+    // maps like "filetype" are merged, so that a theme only needs to
+    // give the colors that differ
+    for (key, value) in variant.iter() {
+        match (data.get_mut(key), value) {
+            (Some(serde_json::Value::Object(map)), serde_json::Value::Object(overrides)) => {
+                for (k, v) in overrides.iter() {
+                    map.insert(k.clone(), v.clone());
+                }
+            }
+            _ => data[key] = value.clone(),
+        }
+    }
+    0
+}
+
+// Returns color code
 fn color_by_name(name: &str) -> Option<u32> {
     lazy_static! {
         static ref COLOR_BY_NAME: HashMap<&'static str, u32> = {
@@ -564,11 +1810,7 @@ fn color_by_name(name: &str) -> Option<u32> {
         };
     }
 
-    if let Some(color) = COLOR_BY_NAME.get(name) {
-        Some(*color)
-    } else {
-        None
-    }
+    COLOR_BY_NAME.get(name).copied()
 }
 
 // Returns filetype index code
@@ -587,10 +1829,20 @@ fn filetype_by_name(name: &str) -> Option<usize> {
         };
     }
 
-    if let Some(filetype) = FILETYPE_BY_NAME.get(name) {
-        Some(*filetype)
-    } else {
-        None
+    FILETYPE_BY_NAME.get(name).copied()
+}
+
+// Returns name of filetype, as used in the config file
+fn filetype_name(filetype: usize) -> &'static str {
+    match filetype {
+        FT_FILE => "file",
+        FT_DIR => "directory",
+        FT_SYMLINK => "symlink",
+        FT_FIFO => "fifo",
+        FT_SOCK => "sock",
+        FT_BLOCKDEV => "blockdev",
+        FT_CHARDEV => "chardev",
+        _ => "file",
     }
 }
 
@@ -607,15 +1859,11 @@ fn filemode_by_name(name: &str) -> Option<usize> {
         };
     }
 
-    if let Some(filemode) = FILEMODE_BY_NAME.get(name) {
-        Some(*filemode)
-    } else {
-        None
-    }
+    FILEMODE_BY_NAME.get(name).copied()
 }
 
 fn load_config_data(data: &serde_json::Value, config_file: &Path) -> Settings {
-    let mut settings = Settings::default();
+    let mut settings = default_settings();
 
     let mut errors = 0u32;
 
@@ -641,94 +1889,535 @@ fn load_config_data(data: &serde_json::Value, config_file: &Path) -> Settings {
             errors += 1;
         }
     }
+    if let Some(group_value) = data.get("group_directories_first") {
+        if let Some(group_bool) = group_value.as_bool() {
+            settings.group_directories_first = group_bool;
+        } else {
+            eprintln!(
+                "{}: 'group_directories_first' should be a boolean: true or false",
+                config_file.to_string_lossy()
+            );
+            errors += 1;
+        }
+    }
+    if let Some(exec_value) = data.get("exec_by_current_user") {
+        if let Some(exec_bool) = exec_value.as_bool() {
+            settings.exec_by_current_user = exec_bool;
+        } else {
+            eprintln!(
+                "{}: 'exec_by_current_user' should be a boolean: true or false",
+                config_file.to_string_lossy()
+            );
+            errors += 1;
+        }
+    }
+    if let Some(binary_value) = data.get("binary_units") {
+        if let Some(binary_bool) = binary_value.as_bool() {
+            settings.si = !binary_bool;
+        } else {
+            eprintln!(
+                "{}: 'binary_units' should be a boolean: true or false",
+                config_file.to_string_lossy()
+            );
+            errors += 1;
+        }
+    }
+    if let Some(fallback_value) = data.get("filetype_color_fallback") {
+        if let Some(fallback_bool) = fallback_value.as_bool() {
+            settings.type_fallback = fallback_bool;
+        } else {
+            eprintln!(
+                "{}: 'filetype_color_fallback' should be a boolean: true or false",
+                config_file.to_string_lossy()
+            );
+            errors += 1;
+        }
+    }
     if let Some(classify_value) = data.get("classify") {
         if let Some(classify_bool) = classify_value.as_bool() {
             settings.classify = classify_bool;
+        } else if classify_value.is_array() {
+            let n_errors;
+            (
+                settings.classify_by_filetype,
+                settings.classify_by_mode,
+                n_errors,
+            ) = load_config_classify(classify_value, config_file);
+            settings.classify = true;
+            errors += n_errors;
         } else {
             eprintln!(
-                "{}: 'classify' should be a boolean: true or false",
+                "{}: 'classify' should be a boolean or a list: [\"ftype\", \"fmode\"]",
                 config_file.to_string_lossy()
             );
             errors += 1;
         }
     }
 
-    if let Some(extension_value) = data.get("extension") {
-        let n_errors;
-        (settings.color_by_extension, n_errors) =
-            load_config_extension(&extension_value, config_file);
-        errors += n_errors;
+    // colors from an included file are loaded first,
+    // so that the colors in this file override them
+    if let Some(include_value) = data.get("include") {
+        let mut visited = Vec::new();
+        if let Ok(path) = fs::canonicalize(config_file) {
+            visited.push(path);
+        }
+        errors += load_config_include(include_value, config_file, &mut settings, &mut visited);
     }
 
-    if let Some(filetype_value) = data.get("filetype") {
-        let n_errors;
-        (settings.color_by_filetype, n_errors) = load_config_filetype(&filetype_value, config_file);
-        errors += n_errors;
-    }
+    errors += load_config_colors(data, config_file, &mut settings);
 
-    if let Some(mode_value) = data.get("mode") {
-        let n_errors;
-        (settings.color_by_mode, n_errors) = load_config_filemode(&mode_value, config_file);
-        errors += n_errors;
+    if let Some(indicators_value) = data.get("indicators") {
+        errors += load_config_indicators(indicators_value, config_file, &mut settings);
     }
 
-    if errors > 0 {
-        std::process::exit(2);
+    if let Some(size_value) = data.get("size_column") {
+        errors += load_config_size_column(
+            size_value,
+            config_file,
+            &mut settings.size_field_by_filetype,
+        );
     }
-    settings
-}
 
-fn load_config_extension(
-    extension_value: &serde_json::Value,
+    if let Some(recent_value) = data.get("recent_days") {
+        match recent_value.as_u64() {
+            Some(days) if days > 0 && days <= i64::MAX as u64 => settings.recent_days = days as i64,
+            _ => {
+                eprintln!(
+                    "{}: 'recent_days' should be a number greater than zero",
+                    config_file.to_string_lossy()
+                );
+                errors += 1;
+            }
+        }
+    }
+    if let Some(row_group_value) = data.get("row_group") {
+        match row_group_value.as_u64() {
+            Some(row_group) if row_group > 0 => settings.row_group = Some(row_group as usize),
+            _ => {
+                eprintln!(
+                    "{}: 'row_group' should be a number greater than zero",
+                    config_file.to_string_lossy()
+                );
+                errors += 1;
+            }
+        }
+    }
+    if let Some(max_open_value) = data.get("max_open") {
+        match max_open_value.as_u64() {
+            Some(max_open) if max_open > 0 => settings.max_open = Some(max_open as usize),
+            _ => {
+                eprintln!(
+                    "{}: 'max_open' should be a number greater than zero",
+                    config_file.to_string_lossy()
+                );
+                errors += 1;
+            }
+        }
+    }
+    if let Some(layout_value) = data.get("layout") {
+        match layout_value.as_str().map(|x| x.to_lowercase()).as_deref() {
+            Some("auto") => settings.layout = Layout::Auto,
+            Some("long") => settings.layout = Layout::Long,
+            Some("wide") => settings.layout = Layout::Wide,
+            _ => {
+                eprintln!(
+                    "{}: 'layout' should be one of: \"auto\", \"long\", \"wide\"",
+                    &config_file.to_string_lossy()
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    // shorthand for the size column of directories
+    if let Some(placeholder_value) = data.get("dir_placeholder") {
+        match placeholder_value
+            .as_str()
+            .map(|x| x.to_lowercase())
+            .as_deref()
+        {
+            Some("placeholder") => settings.size_field_by_filetype[FT_DIR] = SizeField::Placeholder,
+            Some("blank") => settings.size_field_by_filetype[FT_DIR] = SizeField::Blank,
+            Some("size") => settings.size_field_by_filetype[FT_DIR] = SizeField::Size,
+            _ => {
+                eprintln!(
+                    "{}: 'dir_placeholder' should be one of: \"placeholder\", \"blank\", \"size\"",
+                    &config_file.to_string_lossy()
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        std::process::exit(2);
+    }
+    settings
+}
+
+// load the color maps from config data
+// Returns number of errors
+fn load_config_colors(
+    data: &serde_json::Value,
     config_file: &Path,
-) -> (HashMap<String, u32>, u32) {
-    let mut color_map = HashMap::new();
+    settings: &mut Settings,
+) -> u32 {
     let mut errors = 0u32;
 
-    if let Some(extensions) = extension_value.as_object() {
-        for (key, value) in extensions.iter() {
-            if let Some(svalue) = value.as_str() {
-                if let Some(color) = color_by_name(&svalue.to_lowercase()) {
-                    color_map.insert(key.to_lowercase(), color);
-                } else {
+    if let Some(extension_value) = data.get("extension") {
+        errors += load_config_extension(
+            extension_value,
+            config_file,
+            &mut settings.color_by_extension,
+            &mut settings.color_by_pattern,
+        );
+    }
+
+    if let Some(name_value) = data.get("name") {
+        errors += load_config_name(name_value, config_file, &mut settings.color_by_filename);
+    }
+
+    if let Some(filetype_value) = data.get("filetype") {
+        errors +=
+            load_config_filetype(filetype_value, config_file, &mut settings.color_by_filetype);
+    }
+
+    if let Some(mode_value) = data.get("mode") {
+        errors += load_config_filemode(mode_value, config_file, &mut settings.color_by_mode);
+    }
+
+    if let Some(age_value) = data.get("age") {
+        errors += load_config_age(age_value, config_file, &mut settings.color_by_age);
+    }
+
+    if let Some(scale_value) = data.get("size_scale") {
+        errors += load_config_size_scale(scale_value, config_file, &mut settings.size_scale);
+    }
+    errors
+}
+
+// load age buckets: [{"max_age": "1d", "color": "green"}, {"max_age": "7d", "color": "cyan"}]
+// The buckets are tried in this order, so they must go from youngest to oldest
+// Returns number of errors
+fn load_config_age(
+    age_value: &serde_json::Value,
+    config_file: &Path,
+    buckets: &mut Vec<(std::time::Duration, u32)>,
+) -> u32 {
+    let mut errors = 0u32;
+
+    let Some(age_list) = age_value.as_array() else {
+        eprintln!(
+            "dir: {}: 'age' should be a list: [{{\"max_age\": \"7d\", \"color\": \"color\"}}]",
+            &config_file.to_string_lossy()
+        );
+        return 1;
+    };
+
+    let mut new_buckets: Vec<(std::time::Duration, u32)> = Vec::new();
+
+    for (idx, value) in age_list.iter().enumerate() {
+        let max_age = value
+            .get("max_age")
+            .and_then(|x| x.as_str())
+            .and_then(parse_duration);
+        let color = value
+            .get("color")
+            .and_then(|x| x.as_str())
+            .and_then(|x| color_by_name(&x.to_lowercase()));
+
+        match (max_age, color) {
+            (Some(max_age), Some(color)) => {
+                // a bucket after an older one would never be used
+                if new_buckets.last().is_some_and(|(prev, _)| max_age <= *prev) {
                     eprintln!(
-                        "{}: invalid color name: '{}'",
+                        "dir: {}: age bucket {}: max_age should be older than that of the bucket before it",
                         &config_file.to_string_lossy(),
-                        &svalue
+                        idx + 1
                     );
                     errors += 1;
+                    continue;
                 }
-            } else {
+                new_buckets.push((max_age, color));
+            }
+            (None, _) => {
                 eprintln!(
-                    "{}: invalid color string in map 'extension'",
-                    &config_file.to_string_lossy()
+                    "dir: {}: age bucket {}: invalid or missing max_age",
+                    &config_file.to_string_lossy(),
+                    idx + 1
+                );
+                errors += 1;
+            }
+            (_, None) => {
+                eprintln!(
+                    "dir: {}: age bucket {}: unknown or missing color",
+                    &config_file.to_string_lossy(),
+                    idx + 1
                 );
                 errors += 1;
             }
         }
-    } else {
+    }
+
+    // buckets from an included file are replaced as a whole
+    *buckets = new_buckets;
+    errors
+}
+
+// load size scale: {"name": {"max_size": "1M", "color": "green"}}
+// The stops are sorted by max_size, smallest first
+// Returns number of errors
+fn load_config_size_scale(
+    scale_value: &serde_json::Value,
+    config_file: &Path,
+    scale: &mut Vec<(u64, u32)>,
+) -> u32 {
+    let mut errors = 0u32;
+
+    let Some(scale_map) = scale_value.as_object() else {
         eprintln!(
-            "{}: 'extension' should be a map: {{\"ext\": \"color\"}}",
+            "{}: 'size_scale' should be a map: {{\"name\": {{\"max_size\": \"1M\", \"color\": \"color\"}}}}",
+            &config_file.to_string_lossy()
+        );
+        return 1;
+    };
+
+    let mut new_scale = Vec::new();
+
+    for (key, value) in scale_map.iter() {
+        let max_size = value
+            .get("max_size")
+            .and_then(|x| x.as_str())
+            .and_then(parse_size);
+        let color = value
+            .get("color")
+            .and_then(|x| x.as_str())
+            .and_then(|x| color_by_name(&x.to_lowercase()));
+
+        match (max_size, color) {
+            (Some(max_size), Some(color)) => new_scale.push((max_size, color)),
+            (None, _) => {
+                eprintln!(
+                    "{}: size_scale '{}': invalid or missing max_size",
+                    &config_file.to_string_lossy(),
+                    &key
+                );
+                errors += 1;
+            }
+            (_, None) => {
+                eprintln!(
+                    "{}: size_scale '{}': invalid or missing color",
+                    &config_file.to_string_lossy(),
+                    &key
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    new_scale.sort_by_key(|(max_size, _)| *max_size);
+    if new_scale.windows(2).any(|w| w[0].0 == w[1].0) {
+        eprintln!(
+            "{}: size_scale stops should have different max_size",
             &config_file.to_string_lossy()
         );
         errors += 1;
     }
-    (color_map, errors)
+
+    *scale = new_scale;
+    errors
+}
+
+// Returns size in bytes for strings like "512", "100K", "10M", "1G", "2T"
+// These are binary units; "max" is the largest possible size
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("max") {
+        return Some(u64::MAX);
+    }
+    if let Ok(num) = s.parse::<u64>() {
+        return Some(num);
+    }
+
+    let unit = s.chars().last()?.to_ascii_uppercase();
+    let multiplier: u64 = match unit {
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        'T' => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    let num = s[..s.len() - 1].parse::<u64>().ok()?;
+    num.checked_mul(multiplier)
+}
+
+// Returns duration for strings like "30s", "15m", "12h", "7d", "2w"
+fn parse_duration(s: &str) -> Option<std::time::Duration> {
+    let s = s.trim();
+    let unit = s.chars().last()?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let num = s[..s.len() - 1].parse::<u64>().ok()?;
+    Some(std::time::Duration::from_secs(num.checked_mul(multiplier)?))
+}
+
+// load the color maps from an included config file
+// The include path is relative to the including config file
+// Returns number of errors
+fn load_config_include(
+    include_value: &serde_json::Value,
+    config_file: &Path,
+    settings: &mut Settings,
+    visited: &mut Vec<PathBuf>,
+) -> u32 {
+    let Some(include_name) = include_value.as_str() else {
+        eprintln!(
+            "{}: 'include' should be a filename",
+            &config_file.to_string_lossy()
+        );
+        return 1;
+    };
+
+    let include_file = config_file
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(include_name);
+
+    let include_path = match fs::canonicalize(&include_file) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!(
+                "{}: include {}: {}",
+                &config_file.to_string_lossy(),
+                &include_file.to_string_lossy(),
+                e
+            );
+            return 1;
+        }
+    };
+    if visited.contains(&include_path) {
+        eprintln!(
+            "{}: include {}: include loop",
+            &config_file.to_string_lossy(),
+            &include_file.to_string_lossy()
+        );
+        return 1;
+    }
+    visited.push(include_path);
+
+    let data: serde_json::Value = match File::open(&include_file)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+    {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}: {}", &include_file.to_string_lossy(), e);
+            return 1;
+        }
+    };
+
+    let mut errors = 0u32;
+
+    // includes may be nested
+    if let Some(nested_value) = data.get("include") {
+        errors += load_config_include(nested_value, &include_file, settings, visited);
+    }
+    errors += load_config_colors(&data, &include_file, settings);
+    errors
+}
+
+// Returns size field for name
+fn size_field_by_name(name: &str) -> Option<SizeField> {
+    match name {
+        "size" => Some(SizeField::Size),
+        "placeholder" => Some(SizeField::Placeholder),
+        "device" => Some(SizeField::Device),
+        "links" => Some(SizeField::Links),
+        "blank" => Some(SizeField::Blank),
+        _ => None,
+    }
 }
 
-fn load_config_filetype(filetype_value: &serde_json::Value, config_file: &Path) -> (Vec<u32>, u32) {
-    let mut color_map = vec![0; FT_MAX];
+// load classify indicators: {"ftype": "indicator"}
+// Besides filetypes, "exec" sets the indicator for executables
+// Returns number of errors
+fn load_config_indicators(
+    indicators_value: &serde_json::Value,
+    config_file: &Path,
+    settings: &mut Settings,
+) -> u32 {
     let mut errors = 0u32;
 
-    if let Some(filetype) = filetype_value.as_object() {
+    let Some(indicators) = indicators_value.as_object() else {
+        eprintln!(
+            "{}: 'indicators' should be a map: {{\"ftype\": \"indicator\"}}",
+            &config_file.to_string_lossy()
+        );
+        return 1;
+    };
+
+    for (key, value) in indicators.iter() {
+        let Some(indicator) = value.as_str() else {
+            eprintln!(
+                "{}: invalid string in map 'indicators'",
+                &config_file.to_string_lossy()
+            );
+            errors += 1;
+            continue;
+        };
+        // more than one character would mess up the alignment
+        if indicator.graphemes(true).count() != 1 {
+            eprintln!(
+                "{}: indicator for '{}' should be a single character: '{}'",
+                &config_file.to_string_lossy(),
+                &key,
+                &indicator
+            );
+            errors += 1;
+            continue;
+        }
+
+        let name = key.to_lowercase();
+        if name == "exec" {
+            settings.indicator_exec = indicator.to_string();
+        } else if let Some(ftype) = filetype_by_name(&name) {
+            settings.indicator_by_filetype[ftype] = indicator.to_string();
+        } else {
+            eprintln!(
+                "{}: invalid filetype: '{}'",
+                &config_file.to_string_lossy(),
+                &key
+            );
+            errors += 1;
+        }
+    }
+    errors
+}
+
+// load what to show in the size column, per filetype
+// Returns number of errors
+fn load_config_size_column(
+    size_value: &serde_json::Value,
+    config_file: &Path,
+    size_fields: &mut [SizeField],
+) -> u32 {
+    let mut errors = 0u32;
+
+    if let Some(filetype) = size_value.as_object() {
         for (key, value) in filetype.iter() {
             if let Some(ftype) = filetype_by_name(&key.to_lowercase()) {
                 if let Some(svalue) = value.as_str() {
-                    if let Some(color) = color_by_name(&svalue.to_lowercase()) {
-                        color_map[ftype] = color;
+                    if let Some(field) = size_field_by_name(&svalue.to_lowercase()) {
+                        size_fields[ftype] = field;
                     } else {
                         eprintln!(
-                            "{}: invalid color name: '{}'",
+                            "{}: invalid size column: '{}'",
                             &config_file.to_string_lossy(),
                             &svalue
                         );
@@ -736,7 +2425,7 @@ fn load_config_filetype(filetype_value: &serde_json::Value, config_file: &Path)
                     }
                 } else {
                     eprintln!(
-                        "{}: invalid color string in map 'filetype'",
+                        "{}: invalid string in map 'size_column'",
                         &config_file.to_string_lossy()
                     );
                     errors += 1;
@@ -752,87 +2441,309 @@ fn load_config_filetype(filetype_value: &serde_json::Value, config_file: &Path)
         }
     } else {
         eprintln!(
-            "{}: 'filetype' should be a map: {{\"ftype\": \"color\"}}",
+            "{}: 'size_column' should be a map: {{\"ftype\": \"size|placeholder|device|links|blank\"}}",
             &config_file.to_string_lossy()
         );
         errors += 1;
     }
-    (color_map, errors)
+    errors
 }
 
-fn load_config_filemode(mode_value: &serde_json::Value, config_file: &Path) -> (Vec<u32>, u32) {
-    let mut color_map = vec![0; FM_MAX];
+// classify may be a list of filetypes and filemodes that get an indicator
+// Returns allowed filetypes, allowed filemodes, number of errors
+fn load_config_classify(
+    classify_value: &serde_json::Value,
+    config_file: &Path,
+) -> (Vec<bool>, Vec<bool>, u32) {
+    let mut filetypes = vec![false; FT_MAX];
+    let mut filemodes = vec![false; FM_MAX];
     let mut errors = 0u32;
 
-    if let Some(mode) = mode_value.as_object() {
-        for (key, value) in mode.iter() {
-            if let Some(fmode) = filemode_by_name(&key.to_lowercase()) {
-                if let Some(svalue) = value.as_str() {
-                    if let Some(color) = color_by_name(&svalue.to_lowercase()) {
-                        color_map[fmode] = color;
-                    } else {
-                        eprintln!(
-                            "{}: invalid color name: '{}'",
-                            &config_file.to_string_lossy(),
-                            &svalue
-                        );
-                        errors += 1;
-                    }
+    if let Some(names) = classify_value.as_array() {
+        for value in names.iter() {
+            if let Some(svalue) = value.as_str() {
+                let name = svalue.to_lowercase();
+                if let Some(ftype) = filetype_by_name(&name) {
+                    filetypes[ftype] = true;
+                } else if let Some(fmode) = filemode_by_name(&name) {
+                    filemodes[fmode] = true;
                 } else {
                     eprintln!(
-                        "{}: invalid color string in map 'filetype'",
-                        &config_file.to_string_lossy()
+                        "{}: invalid filetype in list 'classify': '{}'",
+                        &config_file.to_string_lossy(),
+                        &svalue
                     );
                     errors += 1;
                 }
             } else {
                 eprintln!(
-                    "{}: invalid filetype: '{}'",
-                    &config_file.to_string_lossy(),
-                    &key
+                    "{}: invalid string in list 'classify'",
+                    &config_file.to_string_lossy()
                 );
                 errors += 1;
             }
         }
     } else {
         eprintln!(
-            "{}: 'mode' should be a map: {{\"fmode\": \"color\"}}",
+            "{}: 'classify' should be a list: [\"ftype\", \"fmode\"]",
             &config_file.to_string_lossy()
         );
         errors += 1;
     }
-    (color_map, errors)
+    (filetypes, filemodes, errors)
 }
 
-#[cfg(windows)]
-fn windows_globbing(args: &[&String]) -> Vec<PathBuf> {
-    let mut v = Vec::new();
+// Returns number of errors
+// Keys that contain wildcards are glob patterns, that are matched against
+// the full filename; these are kept apart from the exact extensions
+fn load_config_extension(
+    extension_value: &serde_json::Value,
+    config_file: &Path,
+    color_map: &mut HashMap<String, u32>,
+    pattern_map: &mut Vec<(glob::Pattern, u32)>,
+) -> u32 {
+    let mut errors = 0u32;
 
-    for arg in args.iter() {
-        let mut glob_iter = glob::glob(*arg).expect("error in file globbing").peekable();
-        if glob_iter.peek().is_none() {
-            // arg is not a globbing pattern
-            // but we wish to see its dir listing anyway, so keep the path
-            v.push(PathBuf::from(*arg));
-            continue;
-        }
-        // expand all globbing
-        for path in glob_iter {
-            match path {
-                Ok(path) => v.push(path),
-                Err(_) => {
-                    // dbg!("I have a problem");
-                    eprintln!("error in file globbing");
-                    continue;
+    if let Some(extensions) = extension_value.as_object() {
+        for (key, value) in extensions.iter() {
+            if let Some(svalue) = value.as_str() {
+                if let Some(color) = color_by_name(&svalue.to_lowercase()) {
+                    if !is_glob_pattern(key) {
+                        color_map.insert(key.to_lowercase(), color);
+                        continue;
+                    }
+
+                    match glob::Pattern::new(key) {
+                        Ok(pattern) => {
+                            // a pattern that is defined again (by include) is replaced
+                            pattern_map.retain(|(p, _)| p.as_str() != pattern.as_str());
+                            pattern_map.push((pattern, color));
+                            // patterns are tried in alphabetical order,
+                            // also after merging an included file
+                            pattern_map.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+                        }
+                        Err(err) => {
+                            eprintln!(
+                                "{}: invalid pattern '{}': {}",
+                                &config_file.to_string_lossy(),
+                                &key,
+                                err
+                            );
+                            errors += 1;
+                        }
+                    }
+                } else {
+                    eprintln!(
+                        "{}: invalid color name: '{}'",
+                        &config_file.to_string_lossy(),
+                        &svalue
+                    );
+                    errors += 1;
                 }
+            } else {
+                eprintln!(
+                    "{}: invalid color string in map 'extension'",
+                    &config_file.to_string_lossy()
+                );
+                errors += 1;
             }
         }
-    }
-    v
-}
-
-fn main() {
-    let matches = Command::new("dir")
+    } else {
+        eprintln!(
+            "{}: 'extension' should be a map: {{\"ext\": \"color\"}}",
+            &config_file.to_string_lossy()
+        );
+        errors += 1;
+    }
+    errors
+}
+
+// load colors for exact filenames, like "Makefile"
+// Returns number of errors
+fn load_config_name(
+    name_value: &serde_json::Value,
+    config_file: &Path,
+    color_map: &mut HashMap<String, u32>,
+) -> u32 {
+    let mut errors = 0u32;
+
+    if let Some(names) = name_value.as_object() {
+        for (key, value) in names.iter() {
+            if let Some(svalue) = value.as_str() {
+                if let Some(color) = color_by_name(&svalue.to_lowercase()) {
+                    color_map.insert(key.to_string(), color);
+                } else {
+                    eprintln!(
+                        "{}: invalid color name: '{}'",
+                        &config_file.to_string_lossy(),
+                        &svalue
+                    );
+                    errors += 1;
+                }
+            } else {
+                eprintln!(
+                    "{}: invalid color string in map 'name'",
+                    &config_file.to_string_lossy()
+                );
+                errors += 1;
+            }
+        }
+    } else {
+        eprintln!(
+            "{}: 'name' should be a map: {{\"filename\": \"color\"}}",
+            &config_file.to_string_lossy()
+        );
+        errors += 1;
+    }
+    errors
+}
+
+fn is_glob_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+// Returns number of errors
+fn load_config_filetype(
+    filetype_value: &serde_json::Value,
+    config_file: &Path,
+    color_map: &mut [u32],
+) -> u32 {
+    let mut errors = 0u32;
+
+    if let Some(filetype) = filetype_value.as_object() {
+        for (key, value) in filetype.iter() {
+            if let Some(ftype) = filetype_by_name(&key.to_lowercase()) {
+                if let Some(svalue) = value.as_str() {
+                    if let Some(color) = color_by_name(&svalue.to_lowercase()) {
+                        color_map[ftype] = color;
+                    } else {
+                        eprintln!(
+                            "{}: invalid color name: '{}'",
+                            &config_file.to_string_lossy(),
+                            &svalue
+                        );
+                        errors += 1;
+                    }
+                } else {
+                    eprintln!(
+                        "{}: invalid color string in map 'filetype'",
+                        &config_file.to_string_lossy()
+                    );
+                    errors += 1;
+                }
+            } else {
+                eprintln!(
+                    "{}: invalid filetype: '{}'",
+                    &config_file.to_string_lossy(),
+                    &key
+                );
+                errors += 1;
+            }
+        }
+    } else {
+        eprintln!(
+            "{}: 'filetype' should be a map: {{\"ftype\": \"color\"}}",
+            &config_file.to_string_lossy()
+        );
+        errors += 1;
+    }
+    errors
+}
+
+// Returns number of errors
+fn load_config_filemode(
+    mode_value: &serde_json::Value,
+    config_file: &Path,
+    color_map: &mut [u32],
+) -> u32 {
+    let mut errors = 0u32;
+
+    if let Some(mode) = mode_value.as_object() {
+        for (key, value) in mode.iter() {
+            if let Some(fmode) = filemode_by_name(&key.to_lowercase()) {
+                if let Some(svalue) = value.as_str() {
+                    if let Some(color) = color_by_name(&svalue.to_lowercase()) {
+                        color_map[fmode] = color;
+                    } else {
+                        eprintln!(
+                            "{}: invalid color name: '{}'",
+                            &config_file.to_string_lossy(),
+                            &svalue
+                        );
+                        errors += 1;
+                    }
+                } else {
+                    eprintln!(
+                        "{}: invalid color string in map 'filetype'",
+                        &config_file.to_string_lossy()
+                    );
+                    errors += 1;
+                }
+            } else {
+                eprintln!(
+                    "{}: invalid filetype: '{}'",
+                    &config_file.to_string_lossy(),
+                    &key
+                );
+                errors += 1;
+            }
+        }
+    } else {
+        eprintln!(
+            "{}: 'mode' should be a map: {{\"fmode\": \"color\"}}",
+            &config_file.to_string_lossy()
+        );
+        errors += 1;
+    }
+    errors
+}
+
+// Returns paths, and number of printed errors
+// Like a shell, a pattern that matches nothing is an error
+// This is always done on Windows, and on UNIX only with --glob
+fn expand_globs(args: &[&String]) -> (Vec<PathBuf>, u32) {
+    let mut v = Vec::new();
+    let mut errors = 0u32;
+
+    for arg in args.iter() {
+        if !is_glob_pattern(arg) {
+            // arg is not a globbing pattern; keep the path as it is
+            v.push(PathBuf::from(*arg));
+            continue;
+        }
+        let glob_iter = match glob::glob(arg) {
+            Ok(x) => x,
+            Err(_) => {
+                // not a valid pattern, like "[abc"; it may be a filename
+                v.push(PathBuf::from(*arg));
+                continue;
+            }
+        };
+        // expand all globbing
+        let mut found = false;
+        for path in glob_iter {
+            match path {
+                Ok(path) => {
+                    v.push(path);
+                    found = true;
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", e.path().to_string_lossy(), e.error());
+                    errors += 1;
+                }
+            }
+        }
+        if !found {
+            eprintln!("{}: no matches found", arg);
+            errors += 1;
+        }
+    }
+    (v, errors)
+}
+
+// Returns the command-line interface definition
+fn build_cli() -> Command {
+    Command::new("dir")
         .color(ColorChoice::Never)
         .version(env!("CARGO_PKG_VERSION"))
         .author("Walter de Jong <walter@heiho.net>")
@@ -844,20 +2755,398 @@ fn main() {
                 .long("all")
                 .action(ArgAction::SetTrue)
                 .help("show all, including hidden"),
+            Arg::new("hidden-only")
+                .long("hidden-only")
+                .action(ArgAction::SetTrue)
+                .help("show only hidden entries (overrides --all)"),
+            Arg::new("long")
+                .short('l')
+                .long("long")
+                .action(ArgAction::SetTrue)
+                .overrides_with_all(["wide", "one"])
+                .help("show listing with details (default when redirected)"),
             Arg::new("wide")
                 .short('w')
                 .long("wide")
                 .action(ArgAction::SetTrue)
-                .help("show listing in columns without details"),
+                .overrides_with_all(["long", "one"])
+                .help("show listing in columns without details (default on a terminal)"),
             Arg::new("one")
                 .short('1')
                 .long("one")
                 .action(ArgAction::SetTrue)
+                .overrides_with_all(["long", "wide"])
                 .help("show only names in one column without details"),
+            Arg::new("print0")
+                .long("print0")
+                .action(ArgAction::SetTrue)
+                .help("list names only, separated by NUL characters"),
+            Arg::new("porcelain")
+                .long("porcelain")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["json", "print0"])
+                .help("stable tab-separated output for scripts"),
+            Arg::new("stat")
+                .long("stat")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "print0",
+                    "porcelain",
+                    "dired",
+                    "dirs-tree",
+                    "tree",
+                    "merge",
+                    "recursive",
+                    "watch",
+                ])
+                .help("show everything that is known about each path, like stat(1)"),
+            Arg::new("diff")
+                .long("diff")
+                .visible_alias("compare-dirs")
+                .value_names(["DIR_A", "DIR_B"])
+                .num_args(2)
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all([
+                    "json",
+                    "print0",
+                    "porcelain",
+                    "dired",
+                    "stat",
+                    "tree",
+                    "dirs-tree",
+                    "merge",
+                    "watch",
+                ])
+                .help("show how two directories differ"),
+            Arg::new("dired")
+                .long("dired")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "json",
+                    "print0",
+                    "porcelain",
+                    "dirs-tree",
+                    "tree",
+                    "watch",
+                    "wide",
+                    "one",
+                ])
+                .help("long listing with byte offsets of names, for Emacs dired"),
+            Arg::new("width")
+                .long("width")
+                .value_name("COLS")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("assume screen width for wide listing"),
+            Arg::new("column-separator")
+                .long("column-separator")
+                .value_name("CHAR")
+                .num_args(0..=1)
+                .default_missing_value("│")
+                .help("draw a line between columns in wide listing"),
+            Arg::new("row-group")
+                .long("row-group")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("put a blank line after every N rows in wide listing"),
+            Arg::new("si")
+                .long("si")
+                .action(ArgAction::SetTrue)
+                .overrides_with("iec")
+                .help("show sizes in powers of 1000 (kB, MB, GB)"),
+            Arg::new("iec")
+                .short('b')
+                .long("iec")
+                .visible_alias("binary")
+                .action(ArgAction::SetTrue)
+                .overrides_with("si")
+                .help("show sizes in powers of 1024 (KiB, MiB, GiB) (default)"),
+            Arg::new("align-sizes")
+                .long("align-sizes")
+                .action(ArgAction::SetTrue)
+                .help("line up the units in the size column"),
+            Arg::new("compact")
+                .long("compact")
+                .action(ArgAction::SetTrue)
+                .help("compact long listing; do not show the date"),
+            Arg::new("author")
+                .long("author")
+                .action(ArgAction::SetTrue)
+                .help("show the author and group of each file"),
+            Arg::new("no-group")
+                .short('G')
+                .long("no-group")
+                .action(ArgAction::SetTrue)
+                .help("do not show the group column (like GNU ls; not colorize like BSD ls)"),
+            Arg::new("columns-show")
+                .long("columns-show")
+                .value_name("COLUMNS")
+                .help("columns to show in long listing (date,perms,author,group,size,sparse,type,name)"),
+            Arg::new("sparse")
+                .long("sparse")
+                .action(ArgAction::SetTrue)
+                .help("mark sparse files, and show how much is allocated"),
+            Arg::new("mime")
+                .long("mime")
+                .action(ArgAction::SetTrue)
+                .help("show content type of files (reads the files)"),
+            Arg::new("accessed")
+                .long("accessed")
+                .action(ArgAction::SetTrue)
+                .overrides_with("created")
+                .help("show and sort on the time of last access"),
+            Arg::new("created")
+                .long("created")
+                .action(ArgAction::SetTrue)
+                .overrides_with("accessed")
+                .help("show and sort on the time of creation"),
+            Arg::new("full-time")
+                .long("full-time")
+                .action(ArgAction::SetTrue)
+                .help("show full date and time"),
+            Arg::new("precision")
+                .long("precision")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u8).range(0..=9))
+                .requires("full-time")
+                .help("with --full-time, show N digits of fractional seconds"),
+            Arg::new("recent-days")
+                .long("recent-days")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("show the time of day for files of last year up to N days old (default 90)"),
+            Arg::new("headers")
+                .long("headers")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-headers")
+                .help("show column headers in long listing"),
+            Arg::new("no-headers")
+                .long("no-headers")
+                .action(ArgAction::SetTrue)
+                .overrides_with("headers")
+                .help("do not show directory names above listings, nor column headers"),
+            Arg::new("color")
+                .long("color")
+                .action(ArgAction::SetTrue)
+                .help("colorize output"),
+            Arg::new("dir-count")
+                .long("dir-count")
+                .action(ArgAction::SetTrue)
+                .help("show number of entries for directories"),
+            Arg::new("dir-size")
+                .long("dir-size")
+                .action(ArgAction::SetTrue)
+                .help("show the total size of the contents of directories"),
+            Arg::new("dir-size-blank")
+                .long("dir-size-blank")
+                .action(ArgAction::SetTrue)
+                .help("show a blank size column for directories instead of <DIR>"),
+            Arg::new("escape-nonprintable-only")
+                .long("escape-nonprintable-only")
+                .action(ArgAction::SetTrue)
+                .overrides_with("literal")
+                .help("escape control characters in names (default on a terminal)"),
+            Arg::new("literal")
+                .long("literal")
+                .action(ArgAction::SetTrue)
+                .overrides_with("escape-nonprintable-only")
+                .help("show names as they are, without escaping"),
+            Arg::new("header-style")
+                .long("header-style")
+                .value_name("STYLE")
+                .value_parser(["slash", "colon", "plain", "raw"])
+                .help("how to show directory names above listings"),
+            Arg::new("raw-headers")
+                .long("raw-headers")
+                .action(ArgAction::SetTrue)
+                .help("show directory names exactly as given (same as --header-style=raw)"),
+            Arg::new("canonical")
+                .long("canonical")
+                .action(ArgAction::SetTrue)
+                .help("in long listing, also show the absolute path with all symlinks resolved"),
+            Arg::new("full-path")
+                .long("full-path")
+                .action(ArgAction::SetTrue)
+                .help("show the path along with the name"),
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("output listing in JSON format"),
+            Arg::new("exec-by-shebang")
+                .long("exec-by-shebang")
+                .action(ArgAction::SetTrue)
+                .help("treat scripts starting with #! as executable"),
+            Arg::new("color-scale")
+                .long("color-scale")
+                .action(ArgAction::SetTrue)
+                .help("color file sizes from small (green) to large (red)"),
+            Arg::new("color-by-age")
+                .long("color-by-age")
+                .action(ArgAction::SetTrue)
+                .help("color by age, as configured in the config file"),
+            Arg::new("background")
+                .long("background")
+                .value_name("WORD")
+                .value_parser(["light", "dark"])
+                .help("pick the light or dark theme from the config file"),
+            Arg::new("hot-seconds")
+                .long("hot-seconds")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64))
+                .help("underline files modified in the last N seconds"),
+            Arg::new("no-type-fallback")
+                .long("no-type-fallback")
+                .visible_alias("no-filetype-color-fallback")
+                .action(ArgAction::SetTrue)
+                .help("do not color regular files that match no extension, name or mode"),
             Arg::new("no-color")
                 .long("no-color")
                 .action(ArgAction::SetTrue)
                 .help("do not colorize output"),
+            Arg::new("classify")
+                .short('F')
+                .long("classify")
+                .action(ArgAction::SetTrue)
+                .help("append indicator to names, like '/' for directories"),
+            Arg::new("no-classify")
+                .long("no-classify")
+                .action(ArgAction::SetTrue)
+                .overrides_with("classify")
+                .help("do not append indicators to names"),
+            Arg::new("recursive")
+                .short('R')
+                .long("recursive")
+                .action(ArgAction::SetTrue)
+                .help("list subdirectories recursively"),
+            Arg::new("relative")
+                .long("relative")
+                .action(ArgAction::SetTrue)
+                .requires("recursive")
+                .conflicts_with("full-path")
+                .help("with -R, show one flat listing of paths relative to the given directory"),
+            Arg::new("dirs-tree")
+                .long("dirs-tree")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["json", "print0", "porcelain", "watch"])
+                .help("show only the tree of directories"),
+            Arg::new("tree")
+                .long("tree")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["json", "print0", "porcelain", "watch", "dirs-tree"])
+                .help("show directories and files as a tree"),
+            Arg::new("indent")
+                .long("indent")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u16))
+                .conflicts_with_all(["json", "print0", "porcelain", "dired", "stat"])
+                .help("put N spaces in front of every line"),
+            Arg::new("depth")
+                .long("depth")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("descend at most N levels deep"),
+            Arg::new("merge")
+                .long("merge")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["recursive", "dirs-tree", "tree"])
+                .help("show the entries of all directories in a single listing"),
+            Arg::new("exclude-dir")
+                .long("exclude-dir")
+                .value_name("NAME")
+                .action(ArgAction::Append)
+                .help("do not descend into directories matching NAME"),
+            Arg::new("match")
+                .long("match")
+                .value_name("PATTERN")
+                .action(ArgAction::Append)
+                .help("show only entries matching PATTERN"),
+            Arg::new("ignore")
+                .long("ignore")
+                .value_name("PATTERN")
+                .action(ArgAction::Append)
+                .help("do not show entries matching PATTERN"),
+            Arg::new("owner")
+                .long("owner")
+                .value_name("USER")
+                .help("show only entries owned by USER (name or uid)"),
+            Arg::new("group")
+                .long("group")
+                .value_name("GROUP")
+                .help("show only entries of GROUP (name or gid)"),
+            Arg::new("perm")
+                .long("perm")
+                .visible_alias("permission-filter")
+                .value_name("MODE")
+                .allow_hyphen_values(true)
+                .help("show only entries with permission bits MODE, -MODE (all) or /MODE (any)"),
+            Arg::new("newer-than-file")
+                .long("newer-than-file")
+                .value_name("FILE")
+                .help("show only entries newer than FILE"),
+            Arg::new("older-than-file")
+                .long("older-than-file")
+                .value_name("FILE")
+                .help("show only entries older than FILE"),
+            Arg::new("ignore-case")
+                .long("ignore-case")
+                .action(ArgAction::SetTrue)
+                .overrides_with("case-sensitive")
+                .help("pattern matching is case-insensitive"),
+            Arg::new("case-sensitive")
+                .long("case-sensitive")
+                .action(ArgAction::SetTrue)
+                .overrides_with("ignore-case")
+                .help("pattern matching is case-sensitive (default)"),
+            Arg::new("progress")
+                .long("progress")
+                .action(ArgAction::SetTrue)
+                .help("show progress of recursive listing on stderr"),
+            Arg::new("watch")
+                .long("watch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["json", "print0"])
+                .help("show listing again whenever it changes"),
+            Arg::new("watch-interval")
+                .long("watch-interval")
+                .value_name("SECS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("watch")
+                .help("with --watch, poll for changes every SECS seconds"),
+            Arg::new("ignore-vanished")
+                .long("ignore-vanished")
+                .action(ArgAction::SetTrue)
+                .help("silently skip files that are removed while listing"),
+            Arg::new("max-open")
+                .long("max-open")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("read at most N directories at the same time"),
+            Arg::new("cache")
+                .long("cache")
+                .action(ArgAction::SetTrue)
+                // --watch redraws exactly when something changed,
+                // which is when a cached listing is most likely stale
+                .conflicts_with("watch")
+                .help("cache directory listings (may show stale metadata)"),
+            Arg::new("dereference")
+                .short('L')
+                .long("dereference")
+                .value_name("LIST")
+                .num_args(0..=1)
+                .require_equals(true)
+                .value_delimiter(',')
+                .value_parser(["type", "size", "time"])
+                .default_missing_values(["type", "size", "time"])
+                .help("show the files that symbolic links point to; optionally only for type,size,time"),
+            Arg::new("keep-broken-links")
+                .long("keep-broken-links")
+                .action(ArgAction::SetTrue)
+                .requires("dereference")
+                .help("with -L, show broken symbolic links as links instead of an error"),
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .action(ArgAction::SetTrue)
+                .help("sort by name; overrides other sort options"),
             Arg::new("size")
                 .short('s')
                 .long("size")
@@ -874,465 +3163,3963 @@ fn main() {
                 .visible_alias("ext")
                 .action(ArgAction::SetTrue)
                 .help("sort by extension"),
+            Arg::new("sort")
+                .long("sort")
+                .value_name("WORD")
+                .action(ArgAction::Append)
+                .value_parser(["name", "size", "time", "extension", "type", "none"])
+                .help("sort by WORD; 'none' lists in directory order; may be repeated"),
+            Arg::new("mixed")
+                .long("mixed")
+                .visible_alias("no-group-directories-first")
+                .action(ArgAction::SetTrue)
+                .overrides_with("dirs-first")
+                .help("sort directories in between files"),
+            Arg::new("dirs-first")
+                .long("dirs-first")
+                .visible_alias("group-directories-first")
+                .action(ArgAction::SetTrue)
+                .overrides_with("mixed")
+                .help("sort directories before files (default)"),
+            Arg::new("symlinks-as-target-type")
+                .long("symlinks-as-target-type")
+                .action(ArgAction::SetTrue)
+                .help("sort symbolic links to directories among directories"),
+            Arg::new("preserve-arg-order")
+                .long("preserve-arg-order")
+                .action(ArgAction::SetTrue)
+                .help("show files given as arguments in the given order"),
             Arg::new("reverse")
                 .short('r')
                 .long("reverse")
                 .action(ArgAction::SetTrue)
                 .help("sort in reverse order"),
+            Arg::new("verbose-config")
+                .long("verbose-config")
+                .action(ArgAction::SetTrue)
+                .help("report which config file is loaded"),
+            Arg::new("summary")
+                .long("summary")
+                .visible_alias("total")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "print0",
+                    "porcelain",
+                    "dired",
+                    "stat",
+                    "tree",
+                    "dirs-tree",
+                ])
+                .help("after each listing, show the number of files and their total size"),
+            Arg::new("show-hidden-count")
+                .long("show-hidden-count")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "json",
+                    "print0",
+                    "porcelain",
+                    "dired",
+                    "stat",
+                    "tree",
+                    "dirs-tree",
+                ])
+                .help("after each listing, show how many hidden entries were left out"),
+            Arg::new("legend")
+                .long("legend")
+                .value_name("WHERE")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("stdout")
+                .value_parser(["stdout", "stderr"])
+                .conflicts_with_all([
+                    "json",
+                    "print0",
+                    "porcelain",
+                    "dired",
+                    "stat",
+                    "tree",
+                    "dirs-tree",
+                ])
+                .help("after each listing, show how many entries there are of each type"),
+            Arg::new("stats")
+                .long("stats")
+                .action(ArgAction::SetTrue)
+                .help("show counts and timings on stderr after listing"),
+            Arg::new("glob")
+                .long("glob")
+                .action(ArgAction::SetTrue)
+                .help("expand glob patterns in the arguments, like a shell (always on Windows)"),
+            Arg::new("no-config")
+                .long("no-config")
+                .visible_alias("zero")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose-config")
+                .help("ignore the config file and environment; use built-in defaults"),
+            Arg::new("generate-completions")
+                .long("generate-completions")
+                .value_name("SHELL")
+                .value_parser(clap::value_parser!(clap_complete::Shell))
+                .hide(true)
+                .help("print shell completion script"),
+            Arg::new("man")
+                .long("man")
+                .action(ArgAction::SetTrue)
+                .hide(true)
+                .help("print man page"),
             Arg::new("path").num_args(0..).default_value("."),
         ])
-        .get_matches();
-    // dbg!(&matches);
+}
 
-    // NOTE I would really like to use OsStr here, but clap won't let me
-    // do a .get_many()::<OsStr> nor OsString
-    // (Yet it is said that clap supports OsStr arguments...? I dunno)
-    let args = matches
+// apply command-line flags to settings
+fn apply_args(matches: &ArgMatches, settings: &mut Settings) {
+    let num_paths = matches
         .get_many::<String>("path")
-        .unwrap()
-        .collect::<Vec<_>>();
-    // dbg!(&args);
-
-    let mut settings = load_config();
+        .map(|x| x.count())
+        .unwrap_or(0);
 
     if matches.get_flag("all") {
         settings.all = true;
     }
+    if matches.get_flag("hidden-only") {
+        settings.hidden_only = true;
+    }
+    if matches.get_flag("long") {
+        settings.long = true;
+    }
     if matches.get_flag("wide") {
         settings.long = false;
     }
-    if matches.get_flag("no-color") {
-        settings.color = false;
+    if let Some(width) = matches.get_one::<u16>("width") {
+        settings.width = Some(*width as usize);
     }
-    if matches.get_flag("one") {
-        settings.one = true;
-        // this also implies these flags;
-        settings.color = false;
-        settings.long = true;
-        settings.classify = false;
+    if let Some(row_group) = matches.get_one::<u32>("row-group") {
+        settings.row_group = Some(*row_group as usize);
     }
-    if matches.get_flag("size") {
-        settings.sort_by_size = true;
+    if let Some(separator) = matches.get_one::<String>("column-separator") {
+        settings.column_separator = Some(separator.clone());
     }
-    if matches.get_flag("time") {
-        settings.sort_by_time = true;
+    // asking for units explicitly also shows totals in units, not blocks
+    if matches.get_flag("si") {
+        settings.si = true;
+        settings.block_size = None;
     }
-    if matches.get_flag("extension") {
-        settings.sort_by_extension = true;
+    if matches.get_flag("iec") {
+        settings.si = false;
+        settings.block_size = None;
     }
-    if matches.get_flag("reverse") {
-        settings.sort_reverse = true;
+    if matches.get_flag("align-sizes") {
+        settings.align_sizes = true;
     }
-    let settings = settings; // remove `mut`
-
-    // it's easier to work with Paths, so
-    // convert Vec<&String> args to Vec<PathBuf>
-    #[cfg(unix)]
-    let arg_paths = args
-        .iter()
-        .map(|s| PathBuf::from(s))
-        .collect::<Vec<PathBuf>>();
-    // on Windows perform file globbing on args
-    #[cfg(windows)]
-    let arg_paths = windows_globbing(&args);
-
-    // we first group the given directory arguments together and list those
-    // then group the files together and list those
-    let dir_paths = arg_paths
-        .iter()
-        .filter(|x| x.is_dir())
-        .map(|x| x.clone())
-        .collect::<Vec<PathBuf>>();
-    let file_paths = arg_paths
-        .iter()
-        .filter(|x| !x.is_dir())
-        .map(|x| x.clone())
-        .collect::<Vec<PathBuf>>();
-
-    let mut errors = 0;
+    if matches.get_flag("compact") {
+        settings.compact = true;
+    }
+    if matches.get_flag("author") {
+        settings.author = true;
+    }
+    if matches.get_flag("no-group") {
+        settings.no_group = true;
+    }
+    if let Some(arg) = matches.get_one::<String>("columns-show") {
+        match parse_columns(arg) {
+            Ok(columns) => settings.show_columns = Some(columns),
+            Err(msg) => {
+                eprintln!("dir: --columns-show: {}", msg);
+                std::process::exit(2);
+            }
+        }
+    }
+    if matches.get_flag("sparse") {
+        settings.sparse = true;
+    }
+    if matches.get_flag("mime") {
+        settings.mime = true;
+    }
+    if matches.get_flag("accessed") {
+        settings.time_source = TimeSource::Accessed;
+    }
+    if matches.get_flag("created") {
+        settings.time_source = TimeSource::Created;
+    }
+    if matches.get_flag("full-time") {
+        settings.full_time = true;
+    }
+    if let Some(days) = matches.get_one::<u32>("recent-days") {
+        settings.recent_days = *days as i64;
+    }
+    if let Some(precision) = matches.get_one::<u8>("precision") {
+        settings.time_precision = *precision as usize;
+    }
+    if matches.get_flag("no-headers") {
+        settings.headers = false;
+        settings.dir_headers = false;
+    }
+    if matches.get_flag("headers") {
+        settings.headers = true;
+    }
+    if matches.get_flag("dir-count") {
+        settings.dir_count = true;
+    }
+    if matches.get_flag("dir-size") {
+        settings.dir_size = true;
+    }
+    if matches.get_flag("dir-size-blank") {
+        settings.size_field_by_filetype[FT_DIR] = SizeField::Blank;
+    }
+    if matches.get_flag("escape-nonprintable-only") {
+        settings.escape_nonprintable = true;
+    }
+    if matches.get_flag("literal") {
+        settings.escape_nonprintable = false;
+    }
+    if let Some(style) = matches.get_one::<String>("header-style") {
+        settings.header_style = match style.as_str() {
+            "plain" | "raw" => HeaderStyle::Raw,
+            "colon" => HeaderStyle::Colon,
+            _ => HeaderStyle::Slash,
+        };
+    }
+    if matches.get_flag("raw-headers") {
+        settings.header_style = HeaderStyle::Raw;
+    }
+    if matches.get_flag("canonical") {
+        settings.canonical = true;
+    }
+    if matches.get_flag("full-path") {
+        settings.full_path = true;
+    }
+    if matches.get_flag("json") {
+        settings.json = true;
+        settings.color = false;
+        settings.classify = false;
+    }
+    // the JSON document never holds escape sequences
+    if matches.get_flag("color") && !settings.json {
+        settings.color = true;
+    }
+    if matches.get_flag("exec-by-shebang") {
+        settings.exec_by_shebang = true;
+    }
+    if matches.get_flag("color-scale") {
+        settings.color_scale = true;
+    }
+    if matches.get_flag("color-by-age") {
+        settings.color_by_age_mode = true;
+    }
+    if matches.get_flag("summary") {
+        settings.summary = true;
+    }
+    if matches.get_flag("show-hidden-count") {
+        settings.show_hidden_count = true;
+    }
+    if let Some(legend) = matches.get_one::<String>("legend") {
+        settings.legend = true;
+        settings.legend_stderr = legend == "stderr";
+    }
+    if let Some(secs) = matches.get_one::<u64>("hot-seconds") {
+        settings.hot_seconds = Some(std::time::Duration::from_secs(*secs));
+    }
+    if matches.get_flag("no-color") {
+        settings.color = false;
+    }
+    if matches.get_flag("no-type-fallback") {
+        settings.type_fallback = false;
+    }
+    if matches.get_flag("classify") {
+        settings.classify = true;
+    }
+    if matches.get_flag("no-classify") {
+        settings.classify = false;
+    }
+    if matches.get_flag("one") {
+        settings.one = true;
+    }
+    if matches.get_flag("recursive") {
+        settings.recursive = true;
+    }
+    if matches.get_flag("relative") {
+        settings.relative = true;
+    }
+    if matches.get_flag("tree") {
+        settings.tree = true;
+    }
+    if matches.get_flag("dirs-tree") {
+        settings.dirs_tree = true;
+    }
+    if let Some(depth) = matches.get_one::<u16>("depth") {
+        settings.depth = Some(*depth as usize);
+    }
+    if let Some(indent) = matches.get_one::<u16>("indent") {
+        settings.indent = *indent as usize;
+    }
+    if matches.get_flag("progress") {
+        settings.progress = true;
+    }
+    if matches.get_flag("ignore-vanished") {
+        settings.ignore_vanished = true;
+    }
+    if matches.get_flag("merge") {
+        settings.merge = true;
+    }
+    if let Some(max_open) = matches.get_one::<u32>("max-open") {
+        settings.max_open = Some(*max_open as usize);
+    }
+    if matches.get_flag("cache") {
+        settings.cache = true;
+    }
+    settings.exclude_dirs = compile_patterns(matches, "exclude-dir");
+    settings.match_patterns = compile_patterns(matches, "match");
+    settings.ignore_patterns = compile_patterns(matches, "ignore");
+    settings.newer_than = reference_time(matches, "newer-than-file", settings.time_source);
+    settings.older_than = reference_time(matches, "older-than-file", settings.time_source);
+    settings.owner_filter = id_filter(matches, "owner", lookup_uid);
+    settings.group_filter = id_filter(matches, "group", lookup_gid);
+    if let Some(spec) = matches.get_one::<String>("perm") {
+        match perm::PermFilter::parse(spec) {
+            Ok(filter) => settings.perm_filter = Some(filter),
+            Err(e) => {
+                eprintln!("dir: --perm {}: {}", spec, e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if matches.get_flag("ignore-case") {
+        settings.ignore_case = true;
+    }
+    if matches.get_flag("case-sensitive") {
+        settings.ignore_case = false;
+    }
+    if let Some(attrs) = matches.get_many::<String>("dereference") {
+        settings.dereference = if matches.get_flag("keep-broken-links") {
+            Dereference::FollowKeepBroken
+        } else {
+            Dereference::Follow
+        };
+        let attrs = attrs.map(|x| x.as_str()).collect::<Vec<_>>();
+        settings.deref_type = attrs.contains(&"type");
+        settings.deref_size = attrs.contains(&"size");
+        settings.deref_time = attrs.contains(&"time");
+    }
+    if matches.get_flag("size") {
+        settings.sort_keys = vec![SortKey::Size];
+    }
+    if matches.get_flag("time") {
+        settings.sort_keys = vec![SortKey::Time];
+    }
+    if matches.get_flag("extension") {
+        settings.sort_keys = vec![SortKey::Extension];
+    }
+    if let Some(words) = matches.get_many::<String>("sort") {
+        // --sort may be repeated; the first key counts most
+        settings.sort_keys.clear();
+        for word in words {
+            match SortKey::from_name(word) {
+                Some(key) => settings.sort_keys.push(key),
+                None => settings.sort_none = true,
+            }
+        }
+    }
+    if matches.get_flag("name") {
+        // wins from the other sort options, so that it can follow an alias like `dir -t`
+        settings.sort_keys = vec![SortKey::Name];
+        settings.sort_none = false;
+    }
+    if matches.get_flag("mixed") {
+        settings.group_directories_first = false;
+    }
+    if matches.get_flag("dirs-first") {
+        settings.group_directories_first = true;
+    }
+    if matches.get_flag("reverse") {
+        settings.sort_reverse = true;
+    }
+    if matches.get_flag("preserve-arg-order") {
+        settings.preserve_arg_order = true;
+    }
+    if matches.get_flag("symlinks-as-target-type") {
+        settings.symlinks_as_target_type = true;
+    }
+    if matches.get_flag("print0") {
+        settings.print0 = true;
+        // like -1, but NUL-terminated
+        settings.one = true;
+        settings.color = false;
+        settings.long = true;
+        settings.classify = false;
+        // there are no headers, so the names must tell where they are
+        if (num_paths > 1 || settings.recursive) && !settings.relative {
+            settings.full_path = true;
+        }
+    }
+    if matches.get_flag("porcelain") {
+        settings.porcelain = true;
+        settings.color = false;
+        settings.classify = false;
+        if (num_paths > 1 || settings.recursive) && !settings.relative {
+            settings.full_path = true;
+        }
+    }
+    if matches.get_flag("dired") {
+        // the offsets are in bytes; escape sequences would be in the way
+        settings.dired = true;
+        settings.long = true;
+        settings.one = false;
+        settings.color = false;
+    }
+}
 
-    errors += list_directories(&dir_paths, &settings);
+fn main() {
+    let matches = build_cli().get_matches();
+    // dbg!(&matches);
 
-    // when listing dirs and files, put a newline in between
-    if dir_paths.len() > 0 && file_paths.len() > 0 {
-        println!("");
+    if let Some(shell) = matches.get_one::<clap_complete::Shell>("generate-completions") {
+        clap_complete::generate(*shell, &mut build_cli(), "dir", &mut io::stdout());
+        return;
+    }
+    if matches.get_flag("man") {
+        let man = clap_mangen::Man::new(build_cli());
+        if let Err(e) = man.render(&mut io::stdout()) {
+            eprintln!("dir: failed to write man page: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // NOTE I would really like to use OsStr here, but clap won't let me
+    // do a .get_many()::<OsStr> nor OsString
+    // (Yet it is said that clap supports OsStr arguments...? I dunno)
+    let args = matches
+        .get_many::<String>("path")
+        .unwrap()
+        .collect::<Vec<_>>();
+    // dbg!(&args);
+
+    let background = matches
+        .get_one::<String>("background")
+        .and_then(|x| Background::from_name(x));
+    // with --no-config, the output does not depend on the user's config
+    let no_config = matches.get_flag("no-config");
+    let mut settings = if no_config {
+        Settings::default()
+    } else {
+        // POSIXLY_CORRECT turns colors off too
+        let may_query = color_wanted(&matches, io::stdout().is_terminal())
+            && std::env::var_os("POSIXLY_CORRECT").is_none();
+        load_config(matches.get_flag("verbose-config"), background, may_query)
+    };
+
+    // explicit command-line flags still override these
+    if !no_config && std::env::var_os("POSIXLY_CORRECT").is_some() {
+        apply_posixly_correct(&mut settings);
+    }
+
+    settings.long = settings.layout.is_long(|| io::stdout().is_terminal());
+
+    // decorations are for humans; when the output is not a terminal,
+    // do not show colors. When the output is redirected to a file,
+    // it is likely to be parsed later, so do not classify either
+    // explicit command-line flags still override these
+    if !io::stdout().is_terminal() {
+        settings.color = false;
+        if stdout_is_file() {
+            settings.classify = false;
+        }
+    }
+
+    apply_args(&matches, &mut settings);
+    let settings = settings; // remove `mut`
+
+    if matches.get_flag("stats") {
+        stats::enable();
+    }
+    if settings.progress {
+        progress::enable();
+    }
+
+    // it's easier to work with Paths, so
+    // convert Vec<&String> args to Vec<PathBuf>
+    // on Windows perform file globbing on args
+    // on UNIX the shell already did that, unless asked for with --glob
+    let (arg_paths, arg_errors) = if cfg!(windows) || matches.get_flag("glob") {
+        expand_globs(&args)
+    } else {
+        (args.iter().map(PathBuf::from).collect::<Vec<PathBuf>>(), 0)
+    };
+
+    // we first group the given directory arguments together and list those
+    // then group the files together and list those
+    let dir_paths = arg_paths
+        .iter()
+        .filter(|x| x.is_dir())
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+    let file_paths = arg_paths
+        .iter()
+        .filter(|x| !x.is_dir())
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+
+    if let Some(dirs) = matches.get_many::<PathBuf>("diff") {
+        let dirs = dirs.collect::<Vec<&PathBuf>>();
+        let errors = compare::compare_dirs(dirs[0], dirs[1], &settings);
+        std::process::exit(if errors > 0 { 2 } else { 0 });
     }
 
-    errors += list_files(&file_paths, &settings);
+    // --stat shows the paths themselves, never the contents of directories
+    if matches.get_flag("stat") {
+        let errors = arg_errors + stat::show_stat(&arg_paths, &settings);
+        std::process::exit(if errors > 0 { 2 } else { 0 });
+    }
+
+    if matches.get_flag("watch") {
+        if !watch::can_watch() {
+            eprintln!("dir: --watch only works on a terminal");
+            std::process::exit(2);
+        }
+        let interval = matches
+            .get_one::<u64>("watch-interval")
+            .map(|x| std::time::Duration::from_secs(*x));
+
+        let result = watch::watch(&arg_paths, settings.recursive, interval, || {
+            show_all(&dir_paths, &file_paths, &settings);
+        });
+        if let Err(e) = result {
+            eprintln!("dir: --watch: {}", e);
+            std::process::exit(2);
+        }
+        std::process::exit(0);
+    }
 
+    let started = std::time::Instant::now();
+    let errors = arg_errors + show_all(&dir_paths, &file_paths, &settings);
+    if matches.get_flag("stats") {
+        stats::print(started.elapsed());
+    }
     if errors > 0 {
         std::process::exit(2);
     }
     std::process::exit(0);
 }
 
-// show directory listings
-// Returns number of printed errors
-fn list_directories(dir_paths: &[PathBuf], settings: &Settings) -> u32 {
-    let mut errors = 0u32;
+// POSIXLY_CORRECT selects `ls` compatible defaults
+fn apply_posixly_correct(settings: &mut Settings) {
+    settings.color = false;
+    settings.classify = false;
+    settings.c_collation = true;
+    settings.block_size = Some(512);
+    // the default time format; like ls, show the time of day
+    // for files of the last six months
+    settings.full_time = false;
+    settings.recent_days = 182;
+}
 
-    for (idx, dir_path) in dir_paths.iter().enumerate() {
-        let mut entries = match list_dir(&dir_path) {
-            Ok(v) => v,
-            Err(e) => {
-                eprintln!("{}: {}", &dir_path.to_string_lossy(), e);
-                errors += 1;
-                continue;
-            }
-        };
+// Returns false if colors will be off because of the command-line
+// The config file is loaded before the flags are applied, and it must
+// know whether to ask the terminal for its background color
+fn color_wanted(matches: &ArgMatches, is_terminal: bool) -> bool {
+    const NO_COLOR_FLAGS: [&str; 6] = ["no-color", "one", "json", "print0", "porcelain", "dired"];
 
-        sort_entries(&mut entries, settings);
+    if NO_COLOR_FLAGS.iter().any(|x| matches.get_flag(x)) {
+        return false;
+    }
+    is_terminal || matches.get_flag("color")
+}
 
-        // when listing multiple directories, show the directory name on top
-        if dir_paths.len() > 1 {
-            let path = dir_path.as_path().to_string_lossy();
-            if path.ends_with(std::path::MAIN_SEPARATOR_STR) {
-                println!("{}", &path);
-            } else {
-                println!("{}{}", &path, std::path::MAIN_SEPARATOR);
+// Returns true if stdout is redirected to a regular file
+#[cfg(unix)]
+fn stdout_is_file() -> bool {
+    use std::os::fd::AsFd;
+
+    io::stdout()
+        .as_fd()
+        .try_clone_to_owned()
+        .and_then(|fd| File::from(fd).metadata())
+        .map(|x| x.is_file())
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn stdout_is_file() -> bool {
+    use std::os::windows::io::AsHandle;
+
+    io::stdout()
+        .as_handle()
+        .try_clone_to_owned()
+        .and_then(|handle| File::from(handle).metadata())
+        .map(|x| x.is_file())
+        .unwrap_or(false)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn stdout_is_file() -> bool {
+    false
+}
+
+// show listings of directories, and then of files
+// Returns number of errors
+fn show_all(dir_paths: &[PathBuf], file_paths: &[PathBuf], settings: &Settings) -> u32 {
+    let mut errors = 0;
+
+    errors += list_directories(dir_paths, settings);
+
+    // when listing dirs and files, put a newline in between
+    // without headers, the output is one flat list
+    if !dir_paths.is_empty()
+        && !file_paths.is_empty()
+        && settings.dir_headers
+        && !settings.json
+        && !settings.print0
+        && !settings.porcelain
+    {
+        print_blank_line(settings);
+    }
+
+    errors += list_files(file_paths, settings);
+    progress::clear();
+
+    if settings.json {
+        json::print_document();
+    }
+    if settings.dired {
+        dired::print_trailer();
+    }
+    errors
+}
+
+// print an empty line in between listings
+fn print_blank_line(settings: &Settings) {
+    progress::clear();
+    if settings.dired {
+        dired::print_blank_line();
+    } else {
+        print_indent(settings);
+        println!();
+    }
+}
+
+// with --indent, every line starts with some spaces
+fn print_indent(settings: &Settings) {
+    if settings.indent > 0 {
+        print!("{:1$}", "", settings.indent);
+    }
+}
+
+fn write_indent(out: &mut impl Write, settings: &Settings) -> io::Result<()> {
+    if settings.indent > 0 {
+        write!(out, "{:1$}", "", settings.indent)?;
+    }
+    Ok(())
+}
+
+// Returns compiled glob patterns given for command-line option
+// An invalid pattern is a fatal error
+fn compile_patterns(matches: &ArgMatches, option: &str) -> Vec<glob::Pattern> {
+    let mut patterns = Vec::new();
+
+    if let Some(args) = matches.get_many::<String>(option) {
+        for arg in args {
+            match glob::Pattern::new(arg) {
+                Ok(pattern) => patterns.push(pattern),
+                Err(e) => {
+                    eprintln!("--{} {}: {}", option, arg, e);
+                    std::process::exit(2);
+                }
             }
         }
+    }
+    patterns
+}
+
+// Returns time of the reference file given with option; this is the
+// same time that is shown for the entries, like with --accessed
+// A missing reference file is a fatal error
+fn reference_time(
+    matches: &ArgMatches,
+    option: &str,
+    source: TimeSource,
+) -> Option<DateTime<Local>> {
+    let path = matches.get_one::<String>(option)?;
+
+    match Entry::from_path(Path::new(path), Dereference::Follow) {
+        Ok(entry) => Some(entry.time(source)),
+        Err(e) => {
+            eprintln!("--{} {}: {}", option, path, e);
+            std::process::exit(2);
+        }
+    }
+}
 
-        show_listing(&entries, &settings);
+// Returns the uid or gid given with option, as a number or by name
+// An unknown name is a fatal error
+fn id_filter(matches: &ArgMatches, option: &str, lookup: fn(&str) -> Option<u32>) -> Option<u32> {
+    let arg = matches.get_one::<String>(option)?;
 
-        // when listing multiple directories, put a newline in between
-        if dir_paths.len() > 1 && idx < dir_paths.len() - 1 {
-            println!("");
+    if let Ok(id) = arg.parse::<u32>() {
+        return Some(id);
+    }
+    match lookup(arg) {
+        Some(id) => Some(id),
+        None => {
+            let what = if option == "owner" { "user" } else { "group" };
+            eprintln!("dir: --{} {}: no such {}", option, arg, what);
+            std::process::exit(2);
         }
     }
-    errors
 }
 
-// show listing of files given on command-line
+// Returns true if the entry passes the --owner and --group filters
+#[cfg(unix)]
+fn filter_owner(entry: &Entry, settings: &Settings) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    settings
+        .owner_filter
+        .is_none_or(|uid| entry.metadata.uid() == uid)
+        && settings
+            .group_filter
+            .is_none_or(|gid| entry.metadata.gid() == gid)
+}
+
+// Returns true if the entry passes the --perm filter
+#[cfg(unix)]
+fn filter_perm(entry: &Entry, settings: &Settings) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    settings
+        .perm_filter
+        .is_none_or(|filter| filter.matches(entry.metadata.permissions().mode()))
+}
+
+// there are no permission bits to filter on
+#[cfg(not(unix))]
+fn filter_perm(_entry: &Entry, settings: &Settings) -> bool {
+    settings.perm_filter.is_none()
+}
+
+// there are no owners to filter on
+#[cfg(not(unix))]
+fn filter_owner(_entry: &Entry, settings: &Settings) -> bool {
+    settings.owner_filter.is_none() && settings.group_filter.is_none()
+}
+
+// Returns true if the entry passes the --newer-than-file and --older-than-file filters
+// The times compared are the ones that are shown, like with --accessed
+fn filter_time(entry: &Entry, settings: &Settings) -> bool {
+    let time = entry.time(settings.time_source);
+    if let Some(newer_than) = settings.newer_than {
+        if time <= newer_than {
+            return false;
+        }
+    }
+    if let Some(older_than) = settings.older_than {
+        if time >= older_than {
+            return false;
+        }
+    }
+    true
+}
+
+// Returns true if name matches any of the glob patterns
+// Case sensitivity is the same for all pattern matching
+fn matches_any(patterns: &[glob::Pattern], name: &OsStr, settings: &Settings) -> bool {
+    let options = glob::MatchOptions {
+        case_sensitive: !settings.ignore_case,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+
+    let name = name.to_string_lossy();
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches_with(&name, options))
+}
+
+// Returns true if the entry passes the --match and --ignore filters
+fn filter_patterns(entry: &Entry, settings: &Settings) -> bool {
+    if !settings.match_patterns.is_empty()
+        && !matches_any(&settings.match_patterns, &entry.name, settings)
+    {
+        return false;
+    }
+    !matches_any(&settings.ignore_patterns, &entry.name, settings)
+}
+
+// show directory listings
 // Returns number of printed errors
-fn list_files(file_paths: &[PathBuf], settings: &Settings) -> u32 {
+fn list_directories(dir_paths: &[PathBuf], settings: &Settings) -> u32 {
     let mut errors = 0u32;
 
-    let mut entries = Vec::new();
-    for file_path in file_paths.iter() {
-        let path = file_path.as_path();
-        let entry = match Entry::from_path(path) {
-            Ok(x) => x,
-            Err(e) => {
-                eprintln!("{}: {}", &path.to_string_lossy(), e);
-                errors += 1;
-                continue;
+    if settings.merge && dir_paths.len() > 1 {
+        return list_merged(dir_paths, settings);
+    }
+
+    if settings.dirs_tree || settings.tree {
+        for (idx, dir_path) in dir_paths.iter().enumerate() {
+            if idx > 0 {
+                print_blank_line(settings);
             }
+            errors += tree::show_tree(dir_path, settings);
+        }
+        return errors;
+    }
+
+    // when listing multiple directories, show the directory name on top
+    // with --relative, the names tell where they are
+    let show_header = (dir_paths.len() > 1 || settings.recursive)
+        && settings.dir_headers
+        && !settings.relative
+        && !settings.json
+        && !settings.print0
+        && !settings.porcelain;
+
+    // directories that are yet to be listed, their depth,
+    // and the directory given on the command-line that they are in
+    // this is used as a stack, so it is in reverse order
+    let mut todo = dir_paths
+        .iter()
+        .rev()
+        .map(|x| (x.clone(), 1, x.clone()))
+        .collect::<Vec<(PathBuf, usize, PathBuf)>>();
+    let mut first = true;
+
+    while let Some((dir_path, depth, top_path)) = todo.pop() {
+        if can_stream_names(settings) {
+            if show_header {
+                if !first {
+                    print_blank_line(settings);
+                }
+                print_dir_header(&dir_path, settings);
+            }
+            first = false;
+
+            // with --progress, every name must be on screen before the indicator
+            // is drawn over it, so then the output is not buffered here
+            let stdout = io::stdout();
+            let capacity = if progress::enabled() { 0 } else { 8192 };
+            let mut out = io::BufWriter::with_capacity(capacity, stdout.lock());
+            match stream_names(&dir_path, &mut out, settings) {
+                Ok(n_errors) => errors += n_errors,
+                Err(e) => {
+                    report_error(&dir_path, &e, settings);
+                    errors += 1;
+                }
+            }
+            continue;
+        }
+
+        let mut entries = match list_dir(&dir_path, settings) {
+            Ok((v, n_errors)) => {
+                errors += n_errors;
+                v
+            }
+            Err(e) => {
+                report_error(&dir_path, &e, settings);
+                errors += 1;
+                continue;
+            }
+        };
+
+        if settings.dir_count && settings.long && !settings.one {
+            count_subdir_entries(&mut entries, settings);
+        }
+        if settings.dir_size && (settings.json || (settings.long && !settings.one)) {
+            sum_subdir_sizes(&mut entries, settings);
+        }
+        if settings.relative {
+            let relative_parent = dir_path.strip_prefix(&top_path).unwrap_or(&dir_path);
+            for entry in entries.iter_mut() {
+                entry.relative_parent = Some(relative_parent.to_path_buf());
+            }
+        }
+
+        sort_entries(&mut entries, settings);
+
+        if show_header {
+            // put a newline in between directories
+            if !first {
+                print_blank_line(settings);
+            }
+
+            print_dir_header(&dir_path, settings);
+        }
+        first = false;
+
+        show_listing(&entries, Some(&dir_path), settings);
+
+        let max_depth_reached = settings.depth.is_some_and(|max| depth >= max);
+        if settings.recursive && !max_depth_reached {
+            // descend into subdirectories, in the same order as they were listed
+            let subdirs = entries
+                .iter()
+                .filter(|x| should_descend(x, settings))
+                .map(|x| (x.path(), depth + 1, top_path.clone()))
+                .collect::<Vec<(PathBuf, usize, PathBuf)>>();
+            todo.extend(subdirs.into_iter().rev());
+        }
+    }
+    errors
+}
+
+// show a single listing of the entries of all directories
+// When a name occurs in more than one directory, the first one wins
+// (like with $PATH); use --full-path to see where entries come from
+// Returns number of printed errors
+fn list_merged(dir_paths: &[PathBuf], settings: &Settings) -> u32 {
+    let mut errors = 0u32;
+
+    let mut seen = std::collections::HashSet::new();
+    let mut entries = Vec::new();
+
+    for dir_path in dir_paths.iter() {
+        match list_dir(dir_path, settings) {
+            Ok((v, n_errors)) => {
+                errors += n_errors;
+                for entry in v {
+                    if seen.insert(entry.name.clone()) {
+                        entries.push(entry);
+                    }
+                }
+            }
+            Err(e) => {
+                report_error(dir_path, &e, settings);
+                errors += 1;
+            }
+        }
+    }
+
+    if settings.dir_count && settings.long && !settings.one {
+        count_subdir_entries(&mut entries, settings);
+    }
+    if settings.dir_size && (settings.json || (settings.long && !settings.one)) {
+        sum_subdir_sizes(&mut entries, settings);
+    }
+
+    sort_entries(&mut entries, settings);
+    show_listing(&entries, None, settings);
+    errors
+}
+
+fn print_dir_header(dir_path: &Path, settings: &Settings) {
+    progress::clear();
+    let path = dir_path.to_string_lossy();
+    if settings.dired {
+        dired::print_header(&path);
+        return;
+    }
+    print_indent(settings);
+    println!("{}", format_dir_header(&path, settings));
+}
+
+// Returns directory name as it is shown above its listing
+fn format_dir_header(path: &str, settings: &Settings) -> String {
+    match settings.header_style {
+        HeaderStyle::Slash => {
+            if path.ends_with(std::path::MAIN_SEPARATOR_STR) {
+                path.to_string()
+            } else {
+                format!("{}{}", path, std::path::MAIN_SEPARATOR)
+            }
+        }
+        HeaderStyle::Raw => path.to_string(),
+        HeaderStyle::Colon => format!("{}:", path),
+    }
+}
+
+// Returns true if names can be printed straight from the directory
+// This is the fastest mode: no sorting, no metadata, nothing is collected
+fn can_stream_names(settings: &Settings) -> bool {
+    // on macOS and Windows, hidden files can not be told apart by name alone
+    let hidden_by_name = settings.all || cfg!(all(unix, not(target_os = "macos")));
+
+    settings.one
+        && !settings.classify
+        && settings.sort_none
+        && !settings.sort_reverse
+        && !settings.recursive
+        && !settings.json
+        && !settings.porcelain
+        && settings.newer_than.is_none()
+        && settings.older_than.is_none()
+        && settings.owner_filter.is_none()
+        && settings.group_filter.is_none()
+        && settings.perm_filter.is_none()
+        && !settings.hidden_only
+        && !settings.summary
+        && !settings.legend
+        && !settings.show_hidden_count
+        && hidden_by_name
+}
+
+// write names as they are read from the directory
+// Returns number of errors
+fn stream_names(
+    dir_path: &Path,
+    out: &mut impl Write,
+    settings: &Settings,
+) -> Result<u32, io::Error> {
+    // reading and writing go hand in hand here; it all counts as reading
+    let started = stats::start();
+    let mut errors = 0u32;
+    let mut num_entries = 0usize;
+
+    for dir_entry in fs::read_dir(dir_path)? {
+        let dir_entry = match dir_entry {
+            Ok(d) => d,
+            Err(err) => {
+                report_error(dir_path, &err, settings);
+                errors += 1;
+                continue;
+            }
+        };
+        num_entries += 1;
+
+        let name = dir_entry.file_name();
+        if !settings.all && name.as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        if !settings.match_patterns.is_empty()
+            && !matches_any(&settings.match_patterns, &name, settings)
+        {
+            continue;
+        }
+        if matches_any(&settings.ignore_patterns, &name, settings) {
+            continue;
+        }
+
+        progress::clear();
+        let result = if settings.full_path {
+            write_name(out, dir_entry.path().as_os_str(), settings)
+        } else {
+            write_name(out, &name, settings)
+        };
+        if result.is_err() {
+            // output was closed
+            break;
+        }
+        progress::add_entry();
+    }
+    let _ = out.flush();
+    stats::add_dir(num_entries);
+    stats::add_io_time(started);
+    progress::add_dir();
+    Ok(errors)
+}
+
+// write name plus line terminator
+// On UNIX, names are written as raw bytes, so that names that are
+// not valid UTF-8 are not mangled. When escaping, bytes that are
+// not valid UTF-8 are escaped like control characters
+#[cfg(unix)]
+fn write_name(out: &mut impl Write, name: &OsStr, settings: &Settings) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    write!(out, "{:1$}", "", settings.indent)?;
+    if settings.escape_nonprintable {
+        for chunk in name.as_bytes().utf8_chunks() {
+            out.write_all(sanitize_name(chunk.valid()).as_bytes())?;
+            for byte in chunk.invalid() {
+                write!(out, "\\x{:02x}", byte)?;
+            }
+        }
+    } else {
+        out.write_all(name.as_bytes())?;
+    }
+    out.write_all(line_end(settings).as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_name(out: &mut impl Write, name: &OsStr, settings: &Settings) -> io::Result<()> {
+    let name = name.to_string_lossy();
+    let name = if settings.escape_nonprintable {
+        sanitize_name(&name)
+    } else {
+        name.to_string()
+    };
+    write!(
+        out,
+        "{:indent$}{}{}",
+        "",
+        name,
+        line_end(settings),
+        indent = settings.indent
+    )
+}
+
+// Returns true if recursive listing should descend into this entry
+fn should_descend(entry: &Entry, settings: &Settings) -> bool {
+    if !entry.metadata.is_dir() {
+        return false;
+    }
+    // do not follow symbolic links to directories; they may cause loops
+    if entry.link_dest.is_some() {
+        return false;
+    }
+    if settings.dereference != Dereference::Never {
+        // the entry may be a dereferenced symlink
+        match fs::symlink_metadata(entry.path()) {
+            Ok(metadata) => {
+                if metadata.is_symlink() {
+                    return false;
+                }
+            }
+            Err(_) => return false,
+        }
+    }
+    if !settings.all && !settings.hidden_only && entry.is_hidden() {
+        return false;
+    }
+
+    !matches_any(&settings.exclude_dirs, &entry.name, settings)
+}
+
+// print error message
+// In JSON mode, the error is also recorded in the JSON document
+fn report_error(path: &Path, err: &io::Error, settings: &Settings) {
+    progress::clear();
+    eprintln!("{}: {}", &path.to_string_lossy(), err);
+    if settings.json {
+        json::add_error(path, err);
+    }
+}
+
+// fill in the number of entries for subdirectories
+fn count_subdir_entries(entries: &mut [Entry], settings: &Settings) {
+    // counting means reading all those directories; spread the work over threads
+    const MIN_CHUNK_SIZE: usize = 16;
+
+    let num_threads = num_worker_threads(settings);
+    let chunk_size = std::cmp::max(entries.len().div_ceil(num_threads), MIN_CHUNK_SIZE);
+
+    std::thread::scope(|scope| {
+        for chunk in entries.chunks_mut(chunk_size) {
+            scope.spawn(move || {
+                for entry in chunk.iter_mut() {
+                    if entry.metadata.is_dir() {
+                        entry.dir_count = count_dir_entries(&entry.path(), settings);
+                    }
+                }
+            });
+        }
+    });
+}
+
+// Returns number of threads for reading subdirectories
+// Each thread has one directory open at a time, so this is also
+// the number of directories that are open at the same time (--max-open)
+fn num_worker_threads(settings: &Settings) -> usize {
+    let num_threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    match settings.max_open.or_else(default_max_open) {
+        Some(max_open) => std::cmp::max(std::cmp::min(num_threads, max_open), 1),
+        None => num_threads,
+    }
+}
+
+// Returns limit on open directories, derived from the limit on open files
+// Half of the file descriptors are left for everything else
+#[cfg(unix)]
+fn default_max_open() -> Option<usize> {
+    let mut limit = std::mem::MaybeUninit::<libc::rlimit>::uninit();
+    // SAFETY: limit is valid memory for one struct rlimit
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, limit.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: getrlimit() succeeded and filled in limit
+    let limit = unsafe { limit.assume_init() };
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+    Some(std::cmp::max(limit.rlim_cur as usize / 2, 1))
+}
+
+#[cfg(not(unix))]
+fn default_max_open() -> Option<usize> {
+    None
+}
+
+// Returns number of entries in directory, or None if it can not be read
+// Hidden entries are only counted with --all
+// An entry that can not be read is skipped, rather than losing the count
+fn count_dir_entries(path: &Path, settings: &Settings) -> Option<usize> {
+    let mut count = 0usize;
+
+    for dir_entry in fs::read_dir(path).ok()? {
+        let Ok(d) = dir_entry else {
+            continue;
+        };
+        progress::add_entry();
+        if !settings.all && dir_entry_is_hidden(&d) {
+            continue;
+        }
+        count += 1;
+    }
+    progress::add_dir();
+    Some(count)
+}
+
+// Returns true if directory entry is hidden
+// On UNIX, the name tells, so there is no need to stat the entry
+#[cfg(all(unix, not(target_os = "macos")))]
+fn dir_entry_is_hidden(d: &fs::DirEntry) -> bool {
+    d.file_name().to_string_lossy().starts_with('.')
+}
+
+// other systems also look at the metadata
+// An entry whose metadata can not be read counts as not hidden
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn dir_entry_is_hidden(d: &fs::DirEntry) -> bool {
+    Entry::from_dir_entry(d, Dereference::Never).is_ok_and(|entry| entry.is_hidden())
+}
+
+// fill in the total size of the contents of subdirectories
+fn sum_subdir_sizes(entries: &mut [Entry], settings: &Settings) {
+    // like counting, summing means reading all those directories
+    const MIN_CHUNK_SIZE: usize = 16;
+
+    let num_threads = num_worker_threads(settings);
+    let chunk_size = std::cmp::max(entries.len().div_ceil(num_threads), MIN_CHUNK_SIZE);
+
+    std::thread::scope(|scope| {
+        for chunk in entries.chunks_mut(chunk_size) {
+            scope.spawn(move || {
+                for entry in chunk.iter_mut() {
+                    if entry.metadata.is_dir() {
+                        entry.recursive_size =
+                            Some(dir_size(&entry.path(), settings).map_err(|e| e.to_string()));
+                    }
+                }
+            });
+        }
+    });
+}
+
+// Returns total (apparent) size of all files under directory
+// Symbolic links are not followed, so there can be no loops
+// What can not be read below the directory is reported, and left out;
+// the total is then that of the parts that could be read
+fn dir_size(path: &Path, settings: &Settings) -> Result<u64, io::Error> {
+    let mut total = 0u64;
+    let mut todo = vec![path.to_path_buf()];
+
+    while let Some(dir_path) = todo.pop() {
+        let dir_entries = match fs::read_dir(&dir_path) {
+            Ok(x) => x,
+            // without the directory itself, there is no total at all
+            Err(err) if dir_path == path => return Err(err),
+            Err(err) => {
+                report_error(&dir_path, &err, settings);
+                continue;
+            }
+        };
+        for dir_entry in dir_entries {
+            let d = match dir_entry {
+                Ok(x) => x,
+                Err(err) => {
+                    report_error(&dir_path, &err, settings);
+                    continue;
+                }
+            };
+            progress::add_entry();
+            let metadata = match d.metadata() {
+                Ok(x) => x,
+                Err(err) => {
+                    report_error(&d.path(), &err, settings);
+                    continue;
+                }
+            };
+            if metadata.is_dir() {
+                todo.push(d.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+        progress::add_dir();
+    }
+    Ok(total)
+}
+
+// show listing of files given on command-line
+// Returns number of printed errors
+fn list_files(file_paths: &[PathBuf], settings: &Settings) -> u32 {
+    let (entries, errors) = file_entries(file_paths, settings);
+    show_listing(&entries, None, settings);
+    errors
+}
+
+// Returns entries for files given on command-line, in the order
+// in which they are shown, and number of printed errors
+fn file_entries(file_paths: &[PathBuf], settings: &Settings) -> (Vec<Entry>, u32) {
+    let mut errors = 0u32;
+    let started = stats::start();
+
+    let mut entries = Vec::new();
+    for file_path in file_paths.iter() {
+        let path = file_path.as_path();
+        let entry = match Entry::from_path(path, settings.dereference) {
+            Ok(x) => x,
+            Err(e) => {
+                report_error(path, &e, settings);
+                errors += 1;
+                continue;
+            }
+        };
+        entries.push(entry);
+    }
+    apply_dereference_list(&mut entries, settings);
+    stats::add_entries(entries.len());
+    stats::add_io_time(started);
+
+    // files are shown in the order given on the command-line
+    // only with --preserve-arg-order; --sort=none keeps that order too
+    if !settings.preserve_arg_order {
+        sort_entries(&mut entries, settings);
+    }
+    (entries, errors)
+}
+
+// sort entries in-place
+fn sort_entries(entries: &mut [Entry], settings: &Settings) {
+    if settings.symlinks_as_target_type {
+        resolve_link_targets(entries);
+    }
+
+    if settings.sort_none {
+        // keep directory order
+        if settings.sort_reverse {
+            entries.reverse();
+        }
+        return;
+    }
+
+    // all sort modes group directories first (unless --mixed)
+    // like for sorting by name, reversing also puts the directories last
+    if settings.sort_reverse {
+        entries.sort_by(|a, b| sorter_keys(b, a, settings));
+    } else {
+        entries.sort_by(|a, b| sorter_keys(a, b, settings));
+    }
+}
+
+// compare by each sort key in turn, until one breaks the tie
+// Entries that are still equal are sorted by name
+fn sorter_keys(a: &Entry, b: &Entry, settings: &Settings) -> Ordering {
+    let mut order = sorter_group_dirs(a, b, settings);
+    for key in settings.sort_keys.iter() {
+        order = order.then_with(|| compare_by_key(a, b, *key, settings));
+    }
+    order.then_with(|| compare_names(&a.name, &b.name, settings))
+}
+
+fn compare_by_key(a: &Entry, b: &Entry, key: SortKey, settings: &Settings) -> Ordering {
+    match key {
+        SortKey::Name => compare_names(&a.name, &b.name, settings),
+        SortKey::Size => a.size().cmp(&b.size()),
+        SortKey::Time => a
+            .time(settings.time_source)
+            .cmp(&b.time(settings.time_source)),
+        SortKey::Extension => compare_extensions(a, b, settings),
+        SortKey::Type => sort_rank_filetype(a, settings).cmp(&sort_rank_filetype(b, settings)),
+    }
+}
+
+// files without extension go before files with an extension
+fn compare_extensions(a: &Entry, b: &Entry, settings: &Settings) -> Ordering {
+    // do not treat dots in directory names as file extension
+    let extension = |entry: &Entry| {
+        if sorts_as_dir(entry, settings) {
+            None
+        } else {
+            get_filename_ext(&entry.name).map(|x| x.to_lowercase())
+        }
+    };
+    extension(a).cmp(&extension(b))
+}
+
+// directories go first, then files, symlinks and special files
+fn sort_rank_filetype(entry: &Entry, settings: &Settings) -> usize {
+    if sorts_as_dir(entry, settings) {
+        return 0;
+    }
+    match metadata_filetype(&entry.metadata) {
+        FT_FILE => 1,
+        filetype => filetype + 1,
+    }
+}
+
+// Returns Less if only a is a directory, Greater if only b is a directory
+// With --mixed, directories are sorted in between files; always Equal
+fn sorter_group_dirs(a: &Entry, b: &Entry, settings: &Settings) -> Ordering {
+    if !settings.group_directories_first {
+        return Ordering::Equal;
+    }
+    match (sorts_as_dir(a, settings), sorts_as_dir(b, settings)) {
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+// Returns true if entry is sorted among directories
+fn sorts_as_dir(entry: &Entry, settings: &Settings) -> bool {
+    entry.metadata.is_dir() || (settings.symlinks_as_target_type && entry.link_to_dir)
+}
+
+// find out which symlinks point at directories
+// This is done once before sorting, rather than over and over in the sorter
+// Dangling links and symlink loops are treated as plain symlinks
+fn resolve_link_targets(entries: &mut [Entry]) {
+    for entry in entries.iter_mut() {
+        if entry.metadata.is_symlink() {
+            // the OS gives up on loops with an error (ELOOP)
+            entry.link_to_dir = fs::metadata(entry.path())
+                .map(|x| x.is_dir())
+                .unwrap_or(false);
+        }
+    }
+}
+
+fn compare_names(a: &OsStr, b: &OsStr, settings: &Settings) -> Ordering {
+    if settings.c_collation {
+        // like the C locale; plain byte order
+        return a.cmp(b);
+    }
+
+    let a_lower = a.to_string_lossy().to_lowercase();
+    let b_lower = b.to_string_lossy().to_lowercase();
+    // names that differ only in case are put in byte order,
+    // so that the order never depends on the order in the directory
+    a_lower.cmp(&b_lower).then_with(|| a.cmp(b))
+}
+
+// Returns true if the entry should be shown
+// With --hidden-only, only the hidden entries are shown, also with --all
+fn filter_hidden(entry: &Entry, settings: &Settings) -> bool {
+    if settings.hidden_only {
+        return entry.is_hidden();
+    }
+    settings.all || !entry.is_hidden()
+}
+
+// Returns true if the entry passes all filters, like --all, --match and --newer-than
+fn filter_entry(entry: &Entry, settings: &Settings) -> bool {
+    filter_hidden(entry, settings)
+        && filter_patterns(entry, settings)
+        && filter_time(entry, settings)
+        && filter_owner(entry, settings)
+        && filter_perm(entry, settings)
+}
+
+// show listing of entries
+// dir_path is the directory that is listed; there is none for files
+// given on the command-line, and for merged listings
+fn show_listing(all_entries: &[Entry], dir_path: Option<&Path>, settings: &Settings) {
+    let started = stats::start();
+    // do not mix the progress indicator with the listing
+    progress::clear();
+
+    // if not option --all, do not show hidden files
+    // Column widths are measured on what remains, so that a long hidden
+    // name widens the columns only when it is shown
+    let entries = all_entries
+        .iter()
+        .filter(|x| filter_entry(x, settings))
+        .collect::<Vec<&Entry>>();
+
+    show_entries(&entries, dir_path, settings);
+    // under --json, the summary is part of the document
+    if settings.summary && !settings.json && !entries.is_empty() {
+        print_summary(&entries, settings);
+    }
+    if settings.legend && !entries.is_empty() {
+        print_legend(&entries, settings);
+    }
+    if settings.show_hidden_count {
+        print_hidden_count(all_entries, settings);
+    }
+    stats::add_format_time(started);
+}
+
+fn show_entries(entries: &[&Entry], dir_path: Option<&Path>, settings: &Settings) {
+    if settings.json {
+        match dir_path {
+            Some(dir_path) => json::add_directory(dir_path, entries, settings.summary),
+            None => json::add_entries(entries),
+        }
+        return;
+    }
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    // an error means that the output was closed
+    let _ = write_entries(&mut out, entries, settings).and_then(|_| out.flush());
+}
+
+fn write_entries(out: &mut impl Write, entries: &[&Entry], settings: &Settings) -> io::Result<()> {
+    // show listing of all entries
+    // if not option --long (equals --wide), show wide listing
+
+    if settings.porcelain {
+        for entry in entries {
+            writeln!(
+                out,
+                "{}",
+                porcelain::format_entry(entry, &raw_display_name(entry, settings))
+            )?;
+        }
+        return Ok(());
+    }
+
+    if settings.one {
+        // show only the names, and the classify indicators
+        // these are written as raw bytes; scripts must get the real names
+        for entry in entries {
+            let mut name = if settings.full_path {
+                entry.path().into_os_string()
+            } else if settings.relative {
+                entry.relative_path().into_os_string()
+            } else {
+                entry.name.clone()
+            };
+            if let Some(token) = classify(entry, settings) {
+                name.push(token);
+            }
+            write_name(out, &name, settings)?;
+        }
+        return Ok(());
+    }
+
+    if !settings.long {
+        return write_wide_listing(out, entries, settings);
+    }
+
+    write_long_listing(out, entries, settings)
+}
+
+// print a note like "(3 hidden)" when hidden entries were left out
+// so that it is clear that the directory holds more than is shown
+fn print_hidden_count(entries: &[Entry], settings: &Settings) {
+    let num_hidden = count_hidden(entries, settings);
+    if num_hidden > 0 {
+        print_indent(settings);
+        println!("({} hidden)", num_hidden);
+    }
+}
+
+// what --summary counts
+#[derive(Default)]
+struct Summary {
+    num_files: usize,
+    num_dirs: usize,
+    num_other: usize,
+    // only regular files add to the total size
+    total_size: u64,
+}
+
+fn summarize(entries: &[&Entry]) -> Summary {
+    let mut summary = Summary::default();
+
+    for entry in entries.iter() {
+        match metadata_filetype(&entry.metadata) {
+            FT_FILE => {
+                summary.num_files += 1;
+                summary.total_size += entry.size();
+            }
+            FT_DIR => summary.num_dirs += 1,
+            _ => summary.num_other += 1,
+        }
+    }
+    summary
+}
+
+// Returns number of hidden entries that are left out
+fn count_hidden(entries: &[Entry], settings: &Settings) -> usize {
+    entries
+        .iter()
+        .filter(|x| x.is_hidden() && !filter_hidden(x, settings))
+        .count()
+}
+
+// print a footer like "12 files, 3 dirs, 4.2 MiB total"
+// Only regular files add to the total size; directories show <DIR>
+fn print_summary(entries: &[&Entry], settings: &Settings) {
+    let Summary {
+        num_files,
+        num_dirs,
+        num_other,
+        total_size,
+    } = summarize(entries);
+
+    let plural = |n: usize, singular: &str, plural: &str| {
+        format!("{} {}", n, if n == 1 { singular } else { plural })
+    };
+    let mut parts = vec![
+        plural(num_files, "file", "files"),
+        plural(num_dirs, "dir", "dirs"),
+    ];
+    if num_other > 0 {
+        parts.push(plural(num_other, "other", "others"));
+    }
+
+    // format_size() shows sizes below one kilobyte without unit
+    let kilo = if settings.si { 1000 } else { 1024 };
+    if let Some(block_size) = settings.block_size {
+        parts.push(format!("{} blocks total", total_size.div_ceil(block_size)));
+    } else if total_size < kilo {
+        parts.push(format!("{} bytes total", total_size));
+    } else {
+        parts.push(format!("{} total", format_size(total_size, settings.si)));
+    }
+
+    print_indent(settings);
+    println!("{}", parts.join(", "));
+}
+
+// print how many entries there are of each filetype
+fn print_legend(entries: &[&Entry], settings: &Settings) {
+    let legend = format_legend(entries);
+    if settings.legend_stderr {
+        eprintln!("{}", legend);
+    } else {
+        print_indent(settings);
+        println!("{}", legend);
+    }
+}
+
+// Returns how many entries there are of each filetype,
+// like "3 dirs, 18 files, 2 symlinks (1 broken)"
+fn format_legend(entries: &[&Entry]) -> String {
+    let mut counts = [0usize; FT_MAX];
+    let mut broken = 0usize;
+
+    for entry in entries.iter() {
+        let filetype = metadata_filetype(&entry.metadata);
+        counts[filetype] += 1;
+        if filetype == FT_SYMLINK && fs::metadata(entry.path()).is_err() {
+            broken += 1;
+        }
+    }
+
+    // in the order in which they are usually listed
+    const LEGEND: [(usize, &str, &str); FT_MAX] = [
+        (FT_DIR, "dir", "dirs"),
+        (FT_FILE, "file", "files"),
+        (FT_SYMLINK, "symlink", "symlinks"),
+        (FT_FIFO, "fifo", "fifos"),
+        (FT_SOCK, "socket", "sockets"),
+        (FT_BLOCKDEV, "block device", "block devices"),
+        (FT_CHARDEV, "char device", "char devices"),
+    ];
+
+    let mut parts = Vec::new();
+    for (filetype, singular, plural) in LEGEND.iter() {
+        let count = counts[*filetype];
+        if count == 0 {
+            continue;
+        }
+        let mut part = format!("{} {}", count, if count == 1 { singular } else { plural });
+        if *filetype == FT_SYMLINK && broken > 0 {
+            part.push_str(&format!(" ({} broken)", broken));
+        }
+        parts.push(part);
+    }
+    parts.join(", ")
+}
+
+// Returns the terminator for lines in the listing
+// Every mode ends the last entry with exactly one newline, except
+// for --print0, where every name ends with a NUL and no newline is printed.
+// An empty listing prints nothing at all
+fn line_end(settings: &Settings) -> &'static str {
+    if settings.print0 {
+        "\0"
+    } else {
+        "\n"
+    }
+}
+
+fn write_long_listing(
+    out: &mut impl Write,
+    entries: &[&Entry],
+    settings: &Settings,
+) -> io::Result<()> {
+    let columns = long_columns(settings);
+
+    // format all fields up front, so that we know how wide the columns are
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            columns
+                .iter()
+                .map(|column| format_column(entry, *column, settings))
+                .collect::<Vec<String>>()
+        })
+        .collect::<Vec<Vec<String>>>();
+    let widths = determine_long_column_widths(&rows, &columns, settings);
+
+    if settings.headers && !entries.is_empty() {
+        if settings.dired {
+            dired::print_line(&format_header(&columns, &widths));
+        } else {
+            write_indent(out, settings)?;
+            writeln!(out, "{}", format_header(&columns, &widths))?;
+        }
+    }
+
+    if settings.dired {
+        for (entry, fields) in entries.iter().zip(rows.iter()) {
+            dired::print_entry(
+                &format_entry_fields(entry, fields, &columns, &widths, settings),
+                &display_name(entry, settings),
+                &format_entry_tail(entry, settings),
+            );
+        }
+        return Ok(());
+    }
+
+    for (entry, fields) in entries.iter().zip(rows.iter()) {
+        write_indent(out, settings)?;
+        writeln!(
+            out,
+            "{}",
+            format_entry(entry, fields, &columns, &widths, settings)
+        )?;
+    }
+    Ok(())
+}
+
+fn write_wide_listing(
+    out: &mut impl Write,
+    entries: &[&Entry],
+    settings: &Settings,
+) -> io::Result<()> {
+    // print in columns
+    // we have variable column widths
+
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let column_widths = determine_column_widths(entries, settings, usable_width(settings));
+    // dbg!(&column_widths);
+
+    // print entries
+
+    let mut num_lines = entries.len() / column_widths.len();
+    if !entries.len().is_multiple_of(column_widths.len()) {
+        num_lines += 1;
+    }
+    let num_lines = num_lines; // remove mut
+
+    for line in 0..num_lines {
+        // with --row-group, put a blank line in between groups of rows
+        if let Some(row_group) = settings.row_group {
+            if line > 0 && line % row_group == 0 {
+                write_indent(out, settings)?;
+                writeln!(out)?;
+            }
+        }
+        write_indent(out, settings)?;
+
+        let mut col = 0;
+        let mut i = line;
+
+        loop {
+            let entry = entries[i];
+
+            let column_width = column_widths[col];
+            col += 1;
+
+            write!(out, "{}", format_wide_entry(entry, settings))?;
+
+            i += num_lines;
+            if i >= entries.len() {
+                break;
+            }
+            if col >= column_widths.len() || column_widths[col] == 0 {
+                break;
+            }
+
+            // note, a name may be wider than the column when the screen is very narrow
+            let mut spacer = column_width.saturating_sub(display_width(entry, settings));
+            if let Some(separator) = &settings.column_separator {
+                // the separator takes the place of the last part of the spacing
+                let sep_width = separator.width() + 1;
+                spacer = spacer.saturating_sub(sep_width);
+                write!(out, "{:<spacer$}{} ", "", separator)?;
+            } else if spacer > 0 {
+                write!(out, "{:<spacer$}", " ")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+// Returns width that is available for the columns of a wide listing
+// Whatever is put in front of every line takes away from the width
+fn usable_width(settings: &Settings) -> usize {
+    std::cmp::max(
+        terminal_width(settings).saturating_sub(line_prefix_width(settings)),
+        1,
+    )
+}
+
+// Returns width of what is put in front of every line
+fn line_prefix_width(settings: &Settings) -> usize {
+    settings.indent
+}
+
+// Returns width of the terminal
+// This is (in order) the --width setting, $COLUMNS, the width of the terminal, or else 80
+fn terminal_width(settings: &Settings) -> usize {
+    if let Some(width) = settings.width {
+        return width;
+    }
+
+    if let Some(width) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|x| *x > 0)
+    {
+        return width;
+    }
+
+    if let Some((terminal_size::Width(w), terminal_size::Height(_))) =
+        terminal_size::terminal_size()
+    {
+        // the terminal may report zero width
+        return std::cmp::max(w as usize, 1);
+    }
+
+    // note, getting the terminal size will fail when output is redirected
+    80usize
+}
+
+#[derive(Debug)]
+struct ColumnInfo {
+    valid: bool,
+    line_length: usize,
+    column_widths: Vec<usize>,
+}
+
+impl ColumnInfo {
+    const SPACER: usize = 2;
+
+    // Returns the spacing between columns
+    // With --column-separator, the separator goes in between the spaces
+    fn spacer(settings: &Settings) -> usize {
+        match &settings.column_separator {
+            Some(separator) => ColumnInfo::SPACER + separator.width(),
+            None => ColumnInfo::SPACER,
+        }
+    }
+
+    fn new() -> ColumnInfo {
+        ColumnInfo {
+            valid: true,
+            line_length: 0,
+            column_widths: Vec::new(),
+        }
+    }
+}
+
+// Returns width of filename on screen, as it is shown after escaping
+fn display_width(entry: &Entry, settings: &Settings) -> usize {
+    let mut width = display_name(entry, settings).width();
+    if let Some(token) = classify(entry, settings) {
+        // an indicator may be a wide character, like an emoji
+        width += token.width();
+    }
+    width
+}
+
+// Returns minimum column width of all entries
+fn determine_min_column_width(entries: &[&Entry], settings: &Settings, term_width: usize) -> usize {
+    let mut min_width = term_width;
+
+    for entry in entries.iter() {
+        let w = display_width(entry, settings);
+        min_width = std::cmp::min(min_width, w + ColumnInfo::spacer(settings));
+    }
+    min_width
+}
+
+// Returns vec of column widths
+fn determine_column_widths(
+    entries: &[&Entry],
+    settings: &Settings,
+    term_width: usize,
+) -> Vec<usize> {
+    let term_width = std::cmp::max(term_width, 1);
+
+    /*
+        The procedure used here to determine the variable column widths
+        is the same as what GNU coreutils `ls` does
+        which is try to fit filenames in columns, while checking the line length
+        If the line length goes over the terminal width, then that's invalid;
+        you can't have as many columns
+        If it does fit, try fitting the next file
+    */
+
+    if entries.len() <= 1 {
+        return vec![term_width];
+    }
+
+    // number of possible columns
+    // there can not be more columns than there are entries
+    let min_width = determine_min_column_width(entries, settings, term_width);
+    let num_possible = std::cmp::min(term_width / min_width, entries.len());
+    if num_possible <= 1 {
+        return vec![term_width];
+    }
+    let mut column_info = Vec::<ColumnInfo>::with_capacity(num_possible);
+    /*
+        make a triangular data structure;
+        column_info[0] has 1 column widths
+        column_info[1] has 2 column widths
+        column_info[2] has 3 column widths
+        and so on
+    */
+    for u in 0..num_possible {
+        column_info.push(ColumnInfo::new());
+        column_info
+            .last_mut()
+            .expect("unexpected memory error")
+            .column_widths = vec![0; u + 1];
+    }
+
+    // determine column widths by fitting entries in
+
+    for (n, entry) in entries.iter().enumerate() {
+        for (i, info) in column_info.iter_mut().enumerate() {
+            if !info.valid {
+                continue;
+            }
+            let col = n / ((entries.len() + i) / (i + 1));
+            let mut width = display_width(entry, settings);
+            if col != i {
+                width += ColumnInfo::spacer(settings);
+            }
+            let width = width; // remove mut
+
+            if width >= info.column_widths[col] {
+                // filename is longer than the column's width;
+                // column needs adjusting
+                let old_column_width = info.column_widths[col];
+                info.column_widths[col] = width;
+                info.line_length += width - old_column_width;
+                // does it still fit onscreen?
+                info.valid = info.line_length < term_width;
+            }
+        }
+    }
+
+    // the highest number of columns is the one that is valid
+    let mut col = 0;
+    for i in (0..num_possible).rev() {
+        if column_info[i].valid {
+            col = i;
+            break;
+        }
+    }
+    // return column widths
+    // NOTE the vec of columns may be larger than the actual number of columns
+    // displayed onscreen; the rightmost columns may have width zero
+    column_info[col].column_widths.clone()
+}
+
+// Returns entries and number of printed errors
+fn list_dir(path: &Path, settings: &Settings) -> Result<(Vec<Entry>, u32), io::Error> {
+    let mut stamp = None;
+    if settings.cache {
+        if let Some(mut entries) = cache::lookup(path, settings.dereference) {
+            apply_dereference_list(&mut entries, settings);
+            progress::add_dir();
+            return Ok((entries, 0));
+        }
+        stamp = cache::stamp(path);
+    }
+    let started = stats::start();
+
+    let mut entries = Vec::new();
+    let mut errors = 0u32;
+
+    for dir_entry in fs::read_dir(path)? {
+        // an fs::DirEntry holds an open file descriptor to the directory
+        // we don't want that ... so therefore I convert it to a custom Entry type
+        // the Entry holds all the same attributes; name, metadata, linkdest (if it is a symbolic link)
+        // but also (attempts) has an easier interface
+        // Mind that the conversion may error, in which case we print the error
+        // and skip this entry
+
+        let entry = match dir_entry {
+            Ok(d) => {
+                match Entry::from_dir_entry(&d, settings.dereference) {
+                    Ok(x) => x,
+                    // the file was removed while we were reading the directory
+                    Err(err)
+                        if settings.ignore_vanished && err.kind() == io::ErrorKind::NotFound =>
+                    {
+                        continue;
+                    }
+                    Err(err) => {
+                        // failed to read this single entry
+                        report_error(&d.path(), &err, settings);
+                        errors += 1;
+                        continue;
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        entries.push(entry);
+        progress::add_entry();
+    }
+
+    // do not cache incomplete listings; errors should be reported again
+    if let Some(mtime) = stamp {
+        if errors == 0 {
+            cache::store(path, settings.dereference, mtime, &entries);
+        }
+    }
+    apply_dereference_list(&mut entries, settings);
+    stats::add_dir(entries.len());
+    stats::add_io_time(started);
+    progress::add_dir();
+    Ok((entries, errors))
+}
+
+// with --dereference=LIST, take only the listed attributes from the link target
+// and the others from the symbolic link itself
+fn apply_dereference_list(entries: &mut [Entry], settings: &Settings) {
+    if settings.deref_type && settings.deref_size && settings.deref_time {
+        return;
+    }
+    for entry in entries.iter_mut() {
+        if entry.other_metadata.is_none() {
+            // not a followed symbolic link
+            continue;
+        }
+        if !settings.deref_type {
+            // show it as the link itself, like without -L
+            let link_dest = match fs::read_link(entry.path()) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if let Some(link_metadata) = entry.other_metadata.take() {
+                entry.other_metadata = Some(std::mem::replace(&mut entry.metadata, link_metadata));
+                entry.link_dest = Some(link_dest);
+            }
+        }
+        // metadata is used for the type, other_metadata is the other end of the link
+        entry.size_from_other = settings.deref_size != settings.deref_type;
+        entry.time_from_other = settings.deref_time != settings.deref_type;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    // settings that do not depend on whether stdout is a terminal
+    fn test_settings() -> Settings {
+        Settings {
+            color: false,
+            escape_nonprintable: false,
+            ..Settings::new()
+        }
+    }
+
+    // Returns sorted entries of directory
+    fn read_sorted(path: &Path, settings: &Settings) -> Vec<Entry> {
+        let (mut entries, errors) = list_dir(path, settings).unwrap();
+        assert_eq!(errors, 0);
+        sort_entries(&mut entries, settings);
+        entries
+    }
+
+    fn shown_names(entries: &[Entry], settings: &Settings) -> Vec<String> {
+        entries.iter().map(|x| display_name(x, settings)).collect()
+    }
+
+    #[test]
+    fn full_path_shows_nested_paths() {
+        let tmp = TempDir::new();
+        tmp.file("sub/deeper/file.txt", 1);
+        let settings = Settings {
+            full_path: true,
+            recursive: true,
+            classify: false,
+            ..test_settings()
+        };
+
+        let sub = tmp.path().join("sub");
+        let deeper = sub.join("deeper");
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(shown_names(&entries, &settings), [sub.to_string_lossy()]);
+        let entries = read_sorted(&sub, &settings);
+        assert_eq!(shown_names(&entries, &settings), [deeper.to_string_lossy()]);
+
+        let entries = read_sorted(&deeper, &settings);
+        let expected = deeper.join("file.txt").to_string_lossy().to_string();
+        assert_eq!(shown_names(&entries, &settings), [expected.as_str()]);
+        // the columns of the wide listing are as wide as the full path
+        assert_eq!(
+            display_width(&entries[0], &settings),
+            expected.chars().count()
+        );
+    }
+
+    fn dirs_and_files() -> TempDir {
+        let tmp = TempDir::new();
+        tmp.dir("bar");
+        tmp.dir("zoo");
+        tmp.file("apple.txt", 1);
+        tmp.file("cherry.txt", 1);
+        tmp
+    }
+
+    #[test]
+    fn mixed_interleaves_directories() {
+        let tmp = dirs_and_files();
+        let settings = Settings {
+            group_directories_first: false,
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["apple.txt", "bar", "cherry.txt", "zoo"]
+        );
+        // directories are still told apart by their indicator
+        assert_eq!(format_wide_entry(&entries[1], &settings), "bar/");
+    }
+
+    // Returns the wide listing of entries, as lines
+    fn wide_listing(entries: &[Entry], settings: &Settings) -> Vec<String> {
+        let entries = entries.iter().collect::<Vec<&Entry>>();
+        let mut buf = Vec::new();
+        write_wide_listing(&mut buf, &entries, settings).unwrap();
+        String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    // ten names of eleven characters each
+    fn ten_files() -> TempDir {
+        let tmp = TempDir::new();
+        for i in 0..10 {
+            tmp.file(&format!("file_{:02}.txt", i), 1);
+        }
+        tmp
+    }
+
+    #[test]
+    fn width_setting_overrides_terminal() {
+        let _lock = crate::testutil::ENV_LOCK.lock().unwrap();
+        std::env::set_var("COLUMNS", "120");
+        let settings = Settings {
+            width: Some(40),
+            ..test_settings()
+        };
+        assert_eq!(terminal_width(&settings), 40);
+        assert_eq!(terminal_width(&test_settings()), 120);
+        std::env::remove_var("COLUMNS");
+    }
+
+    #[test]
+    fn wide_listing_at_width_40() {
+        let tmp = ten_files();
+        let settings = Settings {
+            width: Some(40),
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        // three columns of 11 + 2 spacing fit in 40, four do not
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "file_00.txt  file_04.txt  file_08.txt");
+        assert_eq!(lines[3], "file_03.txt  file_07.txt");
+    }
+
+    #[test]
+    fn wide_listing_at_width_200() {
+        let tmp = ten_files();
+        let settings = Settings {
+            width: Some(200),
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].split_whitespace().count(), 10);
+    }
+
+    #[test]
+    fn wide_listing_at_width_1() {
+        let tmp = ten_files();
+        let settings = Settings {
+            width: Some(1),
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], "file_00.txt");
+    }
+
+    #[test]
+    fn over_wide_name_goes_one_per_line() {
+        let tmp = ten_files();
+        tmp.file(&"x".repeat(60), 1);
+        let settings = Settings {
+            width: Some(40),
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[10], "x".repeat(60));
+    }
+
+    #[test]
+    fn dirs_first_groups_directories() {
+        let tmp = dirs_and_files();
+        let settings = test_settings();
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["bar", "zoo", "apple.txt", "cherry.txt"]
+        );
+    }
+
+    #[test]
+    fn cache_conflicts_with_watch() {
+        let result = build_cli().try_get_matches_from(["dir", "--cache", "--watch"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            clap::error::ErrorKind::ArgumentConflict
+        );
+    }
+
+    fn stream_settings() -> Settings {
+        Settings {
+            one: true,
+            sort_none: true,
+            classify: false,
+            ..test_settings()
+        }
+    }
+
+    #[test]
+    fn stream_names_writes_every_name() {
+        let tmp = ten_files();
+        let settings = stream_settings();
+        assert!(can_stream_names(&settings));
+
+        let mut buf = Vec::new();
+        assert_eq!(stream_names(tmp.path(), &mut buf, &settings).unwrap(), 0);
+        let mut names = String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        assert_eq!(names.len(), 10);
+        assert_eq!(names[0], "file_00.txt");
+    }
+
+    // the maximum throughput mode: `dir -1 --sort=none`
+    // This creates half a million files; run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn stream_names_half_a_million_files() {
+        const NUM_FILES: usize = 500_000;
+
+        let tmp = TempDir::new();
+        for i in 0..NUM_FILES {
+            fs::File::create(tmp.path().join(format!("f{}", i))).unwrap();
+        }
+        let settings = stream_settings();
+
+        // count the lines rather than keeping the output
+        struct LineCounter(usize);
+        impl Write for LineCounter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0 += buf.iter().filter(|x| **x == b'\n').count();
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let started = std::time::Instant::now();
+        let mut out = LineCounter(0);
+        assert_eq!(stream_names(tmp.path(), &mut out, &settings).unwrap(), 0);
+        assert_eq!(out.0, NUM_FILES);
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    // Returns the listing of the entries as it is written
+    fn listing_bytes(entries: &[Entry], settings: &Settings) -> Vec<u8> {
+        let entries = entries.iter().collect::<Vec<&Entry>>();
+        let mut buf = Vec::new();
+        write_entries(&mut buf, &entries, settings).unwrap();
+        buf
+    }
+
+    #[test]
+    fn every_mode_ends_in_one_newline() {
+        let tmp = TempDir::new();
+        tmp.file("a.txt", 1);
+        tmp.file("b.txt", 1);
+
+        let long = Settings {
+            long: true,
+            ..test_settings()
+        };
+        let wide = Settings {
+            long: false,
+            width: Some(80),
+            ..test_settings()
+        };
+        let one = Settings {
+            one: true,
+            ..test_settings()
+        };
+        for settings in [long, wide, one] {
+            let entries = read_sorted(tmp.path(), &settings);
+            let buf = listing_bytes(&entries, &settings);
+            assert!(
+                buf.ends_with(b"b.txt\n"),
+                "{:?}",
+                String::from_utf8_lossy(&buf)
+            );
+        }
+    }
+
+    #[test]
+    fn print0_ends_in_nul() {
+        let tmp = TempDir::new();
+        tmp.file("a.txt", 1);
+        tmp.file("b.txt", 1);
+        let settings = Settings {
+            one: true,
+            print0: true,
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(listing_bytes(&entries, &settings), b"a.txt\0b.txt\0");
+    }
+
+    #[test]
+    fn json_document_ends_in_one_newline() {
+        let mut buf = Vec::new();
+        json::write_document(&mut buf).unwrap();
+        assert!(buf.ends_with(b"}\n"));
+        assert!(!buf.ends_with(b"\n\n"));
+    }
+
+    #[test]
+    fn empty_listing_prints_nothing() {
+        let tmp = TempDir::new();
+        for settings in [
+            test_settings(),
+            Settings {
+                long: false,
+                ..test_settings()
+            },
+            Settings {
+                one: true,
+                ..test_settings()
+            },
+        ] {
+            let entries = read_sorted(tmp.path(), &settings);
+            assert!(listing_bytes(&entries, &settings).is_empty());
+        }
+    }
+
+    #[test]
+    fn completions_for_every_shell() {
+        use clap_complete::Shell;
+
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut buf = Vec::new();
+            clap_complete::generate(shell, &mut build_cli(), "dir", &mut buf);
+            let script = String::from_utf8(buf).unwrap();
+            assert!(script.contains("--all"), "no completions for {}", shell);
+        }
+    }
+
+    // Returns settings as they are after parsing the command-line
+    fn parse_args(args: &[&str]) -> (ArgMatches, Settings) {
+        let matches = build_cli().try_get_matches_from(args).unwrap();
+        let mut settings = test_settings();
+        apply_args(&matches, &mut settings);
+        (matches, settings)
+    }
+
+    #[test]
+    fn cli_definition_is_valid() {
+        build_cli().debug_assert();
+    }
+
+    #[test]
+    fn parse_combined_short_flags() {
+        let (matches, settings) = parse_args(&["dir", "-altr", "src"]);
+        assert!(settings.all);
+        assert!(settings.long);
+        assert_eq!(settings.sort_keys, [SortKey::Time]);
+        assert!(settings.sort_reverse);
+        let paths = matches
+            .get_many::<String>("path")
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["src"]);
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let (matches, settings) = parse_args(&["dir"]);
+        assert!(!settings.all);
+        assert!(settings.sort_keys.is_empty());
+        assert!(!settings.sort_reverse);
+        let paths = matches
+            .get_many::<String>("path")
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["."]);
+    }
+
+    #[test]
+    fn parse_sort_option() {
+        let (_, settings) =
+            parse_args(&["dir", "--sort=size", "--sort=name", "-w", "--width", "40"]);
+        assert_eq!(settings.sort_keys, [SortKey::Size, SortKey::Name]);
+        assert!(!settings.long);
+        assert_eq!(settings.width, Some(40));
+    }
+
+    #[test]
+    fn man_page_has_sections_and_flags() {
+        let mut buf = Vec::new();
+        clap_mangen::Man::new(build_cli()).render(&mut buf).unwrap();
+        let man = String::from_utf8(buf).unwrap();
+        for section in [".SH NAME", ".SH SYNOPSIS", ".SH DESCRIPTION", ".SH OPTIONS"] {
+            assert!(man.contains(section), "missing {}", section);
+        }
+        for flag in ["\\-\\-all", "\\-\\-long", "\\-\\-sort"] {
+            assert!(man.contains(flag), "missing {}", flag);
+        }
+        // hidden options are not documented
+        assert!(!man.contains("generate\\-completions"));
+    }
+
+    const HOUR: std::time::Duration = std::time::Duration::from_secs(3600);
+    const DAY: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+
+    // Returns entry for a new file, last modified `age` ago
+    fn file_aged(tmp: &TempDir, name: &str, age: std::time::Duration) -> Entry {
+        let path = tmp.file(name, 1);
+        crate::testutil::set_mtime(&path, std::time::SystemTime::now() - age);
+        Entry::from_path(&path, Dereference::Never).unwrap()
+    }
+
+    #[test]
+    fn age_buckets() {
+        let config = serde_json::json!([
+            {"max_age": "1d", "color": "green"},
+            {"max_age": "7d", "color": "yellow"},
+        ]);
+        let mut settings = Settings {
+            color: true,
+            color_by_age_mode: true,
+            ..test_settings()
+        };
+        let errors = load_config_age(&config, Path::new("dir.json"), &mut settings.color_by_age);
+        assert_eq!(errors, 0);
+
+        let tmp = TempDir::new();
+        let fresh = file_aged(&tmp, "fresh", HOUR);
+        let week = file_aged(&tmp, "week", 3 * DAY);
+        let old = file_aged(&tmp, "old", 30 * DAY);
+        assert_eq!(color_by_age(&fresh, &settings), color_by_name("green"));
+        assert_eq!(color_by_age(&week, &settings), color_by_name("yellow"));
+        assert_eq!(color_by_age(&old, &settings), None);
+    }
+
+    #[test]
+    fn age_buckets_are_validated() {
+        let config = serde_json::json!([
+            {"max_age": "1d", "color": "green"},
+            {"max_age": "soon", "color": "green"},
+            {"max_age": "7d", "color": "red"},
+        ]);
+        let mut buckets = Vec::new();
+        let errors = load_config_age(&config, Path::new("dir.json"), &mut buckets);
+        assert_eq!(errors, 1);
+        assert_eq!(buckets.len(), 2);
+
+        // a map has no order
+        let config = serde_json::json!({"fresh": {"max_age": "1d", "color": "green"}});
+        assert_eq!(
+            load_config_age(&config, Path::new("dir.json"), &mut buckets),
+            1
+        );
+    }
+
+    #[test]
+    fn age_buckets_must_be_ordered() {
+        let config = serde_json::json!([
+            {"max_age": "7d", "color": "yellow"},
+            {"max_age": "1d", "color": "green"},
+            {"max_age": "7d", "color": "red"},
+            {"max_age": "30d", "color": "blue"},
+        ]);
+        let mut buckets = Vec::new();
+        let errors = load_config_age(&config, Path::new("dir.json"), &mut buckets);
+        // the 1d bucket comes after the 7d one, and 7d is there twice
+        assert_eq!(errors, 2);
+        // the buckets are not reordered
+        assert_eq!(
+            buckets,
+            [
+                (7 * DAY, color_by_name("yellow").unwrap()),
+                (30 * DAY, color_by_name("blue").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn age_bucket_with_unknown_color() {
+        let config = serde_json::json!([
+            {"max_age": "1d", "color": "no-such-color"},
+            {"max_age": "7d"},
+            {"max_age": "30d", "color": "green"},
+        ]);
+        let mut buckets = Vec::new();
+        let errors = load_config_age(&config, Path::new("dir.json"), &mut buckets);
+        assert_eq!(errors, 2);
+        assert_eq!(buckets, [(30 * DAY, color_by_name("green").unwrap())]);
+    }
+
+    #[test]
+    fn format_size_si_and_iec() {
+        assert_eq!(format_size(1_000_000, true), "1.0 MB");
+        assert_eq!(format_size(1_048_576, false), "1.0 MiB");
+        assert_eq!(format_size(1_048_576, true), "1.0 MB");
+        assert_eq!(format_size(1_000_000, false), "976.6 KiB");
+        assert_eq!(format_size(1500, true), "1.5 kB");
+
+        let (_, settings) = parse_args(&["dir", "--si"]);
+        assert!(settings.si);
+    }
+
+    #[test]
+    fn format_size_unit_boundaries() {
+        assert_eq!(format_size(999, true), "999");
+        assert_eq!(format_size(1000, true), "1.0 kB");
+        assert_eq!(format_size(1023, false), "1023");
+        assert_eq!(format_size(1024, false), "1.0 KiB");
+        assert_eq!(format_size(1000, false), "1000");
+        assert_eq!(format_size(1023, true), "1.0 kB");
+        // what would round up to "1024.0 KiB" is shown in the next unit
+        assert_eq!(format_size(1_048_575, false), "1.0 MiB");
+        assert_eq!(format_size(999_999, true), "1.0 MB");
+    }
+
+    #[test]
+    fn config_dir_from_environment() {
+        let _lock = crate::testutil::ENV_LOCK.lock().unwrap();
+        let saved_config = std::env::var_os("DIR_CONFIG");
+        let saved_home = std::env::var_os("DIR_CONFIG_HOME");
+
+        let tmp = TempDir::new();
+        std::env::remove_var("DIR_CONFIG");
+        std::env::set_var("DIR_CONFIG_HOME", tmp.path());
+        assert_eq!(
+            config_file_path(),
+            Some(tmp.path().join("dir").join("dir.json"))
+        );
+
+        // $DIR_CONFIG names the file itself, and goes first
+        let config_file = tmp.path().join("other.json");
+        std::env::set_var("DIR_CONFIG", &config_file);
+        assert_eq!(config_file_path(), Some(config_file));
+
+        // empty is the same as not set
+        std::env::set_var("DIR_CONFIG", "");
+        std::env::set_var("DIR_CONFIG_HOME", "");
+        assert_eq!(
+            config_file_path(),
+            dirs::config_dir().map(|x| x.join("dir").join("dir.json"))
+        );
+
+        for (name, value) in [
+            ("DIR_CONFIG", saved_config),
+            ("DIR_CONFIG_HOME", saved_home),
+        ] {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo_has_blank_size() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new();
+        tmp.file("data", 1234);
+        let fifo = tmp.path().join("fifo");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) }, 0);
+
+        let settings = test_settings();
+        let entries = read_sorted(tmp.path(), &settings);
+        let fifo_entry = entries.iter().find(|x| x.name == "fifo").unwrap();
+        assert_eq!(format_size_field(fifo_entry, &settings), "");
+
+        // time, permissions, and name; no size in between
+        let listing = String::from_utf8(listing_bytes(&entries, &settings)).unwrap();
+        let line = listing.lines().find(|x| x.contains("fifo")).unwrap();
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        assert_eq!(fields.len(), 5, "{:?}", line);
+        assert!(fields[3].starts_with('p'));
+    }
+
+    #[test]
+    fn shebang_is_read_again_when_file_changes() {
+        let tmp = TempDir::new();
+        let path = tmp.path().join("script");
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        let entry = Entry::from_path(&path, Dereference::Never).unwrap();
+        assert!(has_shebang(&entry));
+
+        // as when --watch lists the directory again
+        fs::write(&path, "plain text\n").unwrap();
+        let entry = Entry::from_path(&path, Dereference::Never).unwrap();
+        assert!(!has_shebang(&entry));
+    }
+
+    #[test]
+    fn size_scale_buckets() {
+        let config = serde_json::json!({
+            "small": {"max_size": "1K", "color": "green"},
+            "medium": {"max_size": "10K", "color": "yellow"},
+            "large": {"max_size": "max", "color": "red"},
+        });
+        let mut settings = Settings {
+            color: true,
+            color_scale: true,
+            ..test_settings()
+        };
+        let errors =
+            load_config_size_scale(&config, Path::new("dir.json"), &mut settings.size_scale);
+        assert_eq!(errors, 0);
+
+        let tmp = TempDir::new();
+        let entry = |name: &str, size: usize| {
+            Entry::from_path(&tmp.file(name, size), Dereference::Never).unwrap()
+        };
+        let expected = |name: &str| format_color(color_by_name(name).unwrap(), settings.bold);
+        assert_eq!(
+            size_scale_color(&entry("a", 1024), Column::Size, &settings),
+            expected("green")
+        );
+        assert_eq!(
+            size_scale_color(&entry("b", 5000), Column::Size, &settings),
+            expected("yellow")
+        );
+        assert_eq!(
+            size_scale_color(&entry("c", 50_000), Column::Size, &settings),
+            expected("red")
+        );
+
+        // only the sizes of regular files are colored
+        let dir = Entry::from_path(&tmp.dir("sub"), Dereference::Never).unwrap();
+        assert_eq!(size_scale_color(&dir, Column::Size, &settings), None);
+        assert_eq!(
+            size_scale_color(&entry("d", 10), Column::Time, &settings),
+            None
+        );
+    }
+
+    #[test]
+    fn header_styles() {
+        let header = |style: &str, path: &str| {
+            let (_, settings) = parse_args(&["dir", "--header-style", style]);
+            format_dir_header(path, &settings)
+        };
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(header("slash", "src"), format!("src{}", sep));
+        // no double slash
+        assert_eq!(
+            header("slash", &format!("src{}", sep)),
+            format!("src{}", sep)
+        );
+        assert_eq!(header("colon", "src"), "src:");
+        assert_eq!(header("plain", "src"), "src");
+        // the default stays as it was
+        assert_eq!(
+            format_dir_header("src", &test_settings()),
+            format!("src{}", sep)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_name_is_written_as_is() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new();
+        // "café" in Latin-1
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(tmp.path().join(name), "").unwrap();
+
+        let settings = Settings {
+            one: true,
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(listing_bytes(&entries, &settings), b"caf\xe9.txt\n");
+
+        let settings = Settings {
+            print0: true,
+            ..settings
+        };
+        assert_eq!(listing_bytes(&entries, &settings), b"caf\xe9.txt\0");
+
+        let settings = stream_settings();
+        let mut buf = Vec::new();
+        stream_names(tmp.path(), &mut buf, &settings).unwrap();
+        assert_eq!(buf, b"caf\xe9.txt\n");
+    }
+
+    #[test]
+    fn one_per_line_escapes_control_characters() {
+        let tmp = TempDir::new();
+        tmp.file("red\x1b[31mname", 1);
+        let settings = Settings {
+            one: true,
+            escape_nonprintable: true,
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(listing_bytes(&entries, &settings), b"red\\x1b[31mname\n");
+
+        let settings = Settings {
+            escape_nonprintable: true,
+            ..stream_settings()
+        };
+        let mut buf = Vec::new();
+        stream_names(tmp.path(), &mut buf, &settings).unwrap();
+        assert_eq!(buf, b"red\\x1b[31mname\n");
+
+        // when not escaping, scripts get the real name
+        let settings = stream_settings();
+        let mut buf = Vec::new();
+        stream_names(tmp.path(), &mut buf, &settings).unwrap();
+        assert_eq!(buf, b"red\x1b[31mname\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn one_per_line_escapes_invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new();
+        fs::write(tmp.path().join(OsStr::from_bytes(b"caf\xe9")), "").unwrap();
+        let settings = Settings {
+            escape_nonprintable: true,
+            ..stream_settings()
+        };
+        let mut buf = Vec::new();
+        stream_names(tmp.path(), &mut buf, &settings).unwrap();
+        assert_eq!(buf, b"caf\\xe9\n");
+    }
+
+    #[test]
+    fn porcelain_escapes_only_once() {
+        let tmp = TempDir::new();
+        tmp.file("tab\there", 1);
+        let settings = Settings {
+            porcelain: true,
+            escape_nonprintable: true,
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        let listing = String::from_utf8(listing_bytes(&entries, &settings)).unwrap();
+        assert!(listing.ends_with("\ttab\\there\n"), "{:?}", listing);
+    }
+
+    #[test]
+    fn preserve_arg_order() {
+        let tmp = TempDir::new();
+        let args = ["c.txt", "a.txt", "b.txt"]
+            .iter()
+            .map(|x| tmp.file(x, 1))
+            .collect::<Vec<PathBuf>>();
+
+        let names = |settings: &Settings| {
+            let (entries, errors) = file_entries(&args, settings);
+            assert_eq!(errors, 0);
+            entries
+                .iter()
+                .map(|x| x.name.to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        let (_, settings) = parse_args(&["dir", "--preserve-arg-order"]);
+        assert_eq!(names(&settings), ["c.txt", "a.txt", "b.txt"]);
+        let (_, settings) = parse_args(&["dir", "--sort=none"]);
+        assert_eq!(names(&settings), ["c.txt", "a.txt", "b.txt"]);
+        assert_eq!(names(&test_settings()), ["a.txt", "b.txt", "c.txt"]);
+    }
+
+    // directories "big" and "small", and files of different sizes and ages
+    fn sizes_and_times() -> TempDir {
+        let tmp = TempDir::new();
+        let now = std::time::SystemTime::now();
+        for (name, size, age) in [("huge", 5000, 3), ("tiny", 10, 1), ("medium", 500, 2)] {
+            let path = tmp.file(name, size);
+            crate::testutil::set_mtime(&path, now - age * HOUR);
+        }
+        for (name, age) in [("big", 4), ("small", 0)] {
+            let path = tmp.dir(name);
+            crate::testutil::set_mtime(&path, now - age * HOUR);
+        }
+        tmp
+    }
+
+    #[test]
+    fn size_sort_groups_directories() {
+        let tmp = sizes_and_times();
+        let (_, settings) = parse_args(&["dir", "--sort=size"]);
+        let entries = read_sorted(tmp.path(), &settings);
+        let names = shown_names(&entries, &settings);
+        // the size of a directory depends on the filesystem
+        let mut dirs = names[..2].to_vec();
+        dirs.sort();
+        assert_eq!(dirs, ["big", "small"]);
+        assert_eq!(names[2..], ["tiny", "medium", "huge"]);
+    }
+
+    #[test]
+    fn time_sort_mixed() {
+        let tmp = sizes_and_times();
+        let (_, settings) = parse_args(&["dir", "--sort=time", "--mixed"]);
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["big", "huge", "medium", "tiny", "small"]
+        );
+    }
+
+    #[test]
+    fn directory_positions_in_every_sort_mode() {
+        let tmp = sizes_and_times();
+        let is_dir = |name: &String| name == "big" || name == "small";
+
+        for sort in ["name", "size", "time", "extension", "type"] {
+            let option = format!("--sort={}", sort);
+            let (_, settings) = parse_args(&["dir", &option]);
+            let names = shown_names(&read_sorted(tmp.path(), &settings), &settings);
+            assert!(names[..2].iter().all(is_dir), "{}: {:?}", sort, names);
+
+            // reversed, the directories go last
+            let (_, settings) = parse_args(&["dir", &option, "-r"]);
+            let names = shown_names(&read_sorted(tmp.path(), &settings), &settings);
+            assert!(names[3..].iter().all(is_dir), "{} -r: {:?}", sort, names);
+        }
+    }
+
+    #[test]
+    fn no_background_query_without_colors() {
+        let wanted = |args: &[&str], is_terminal: bool| {
+            let matches = build_cli().try_get_matches_from(args).unwrap();
+            color_wanted(&matches, is_terminal)
+        };
+        assert!(wanted(&["dir"], true));
+        assert!(!wanted(&["dir"], false));
+        assert!(wanted(&["dir", "--color"], false));
+        for flag in [
+            "--no-color",
+            "-1",
+            "--json",
+            "--print0",
+            "--porcelain",
+            "--dired",
+        ] {
+            assert!(!wanted(&["dir", flag], true), "{}", flag);
+        }
+    }
+
+    #[test]
+    fn layout_depends_on_terminal() {
+        assert!(!Layout::Auto.is_long(|| true));
+        assert!(Layout::Auto.is_long(|| false));
+        for is_terminal in [true, false] {
+            assert!(Layout::Long.is_long(|| is_terminal));
+            assert!(!Layout::Wide.is_long(|| is_terminal));
+        }
+
+        // flags override the layout
+        let mut settings = test_settings();
+        settings.long = Layout::Auto.is_long(|| true);
+        let matches = build_cli().try_get_matches_from(["dir", "-l"]).unwrap();
+        apply_args(&matches, &mut settings);
+        assert!(settings.long);
+
+        settings.long = Layout::Auto.is_long(|| false);
+        let matches = build_cli().try_get_matches_from(["dir", "-w"]).unwrap();
+        apply_args(&matches, &mut settings);
+        assert!(!settings.long);
+    }
+
+    // files for the sort chains; a.rs is newer than z.rs
+    fn sort_chain_files() -> TempDir {
+        let tmp = TempDir::new();
+        let now = std::time::SystemTime::now();
+        for (name, size, age) in [
+            ("z.rs", 100, 2),
+            ("a.rs", 100, 1),
+            ("m.rs", 50, 3),
+            ("b.txt", 10, 4),
+            ("c.txt", 5, 5),
+        ] {
+            let path = tmp.file(name, size);
+            crate::testutil::set_mtime(&path, now - age * HOUR);
+        }
+        tmp
+    }
+
+    #[test]
+    fn sort_by_two_keys() {
+        let tmp = sort_chain_files();
+        let (_, settings) = parse_args(&["dir", "--sort=extension", "--sort=size"]);
+
+        let entries = read_sorted(tmp.path(), &settings);
+        // equal extension and size falls back to the name
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["m.rs", "a.rs", "z.rs", "c.txt", "b.txt"]
+        );
+    }
+
+    #[test]
+    fn sort_by_three_keys() {
+        let tmp = sort_chain_files();
+        let (_, mut settings) =
+            parse_args(&["dir", "--sort=extension", "--sort=size", "--sort=time"]);
+
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["m.rs", "z.rs", "a.rs", "c.txt", "b.txt"]
+        );
+
+        // reversing reverses the whole chain
+        settings.sort_reverse = true;
+        let entries = read_sorted(tmp.path(), &settings);
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["b.txt", "c.txt", "a.rs", "z.rs", "m.rs"]
+        );
+    }
+
+    #[test]
+    fn wide_time_sort_is_the_same_every_run() {
+        let tmp = ten_files();
+        // all files have the same mtime, so only the name breaks the tie
+        let time = std::time::SystemTime::now() - HOUR;
+        for entry in std::fs::read_dir(tmp.path()).unwrap() {
+            crate::testutil::set_mtime(&entry.unwrap().path(), time);
+        }
+        let (_, settings) = parse_args(&["dir", "-w", "-t", "--width", "40"]);
+
+        let (mut entries, _) = list_dir(tmp.path(), &settings).unwrap();
+        sort_entries(&mut entries, &settings);
+        let first = listing_bytes(&entries, &settings);
+
+        // whatever order the directory gives, the output is the same
+        for _ in 0..3 {
+            let (mut entries, _) = list_dir(tmp.path(), &settings).unwrap();
+            entries.reverse();
+            sort_entries(&mut entries, &settings);
+            assert_eq!(listing_bytes(&entries, &settings), first);
+            entries.rotate_left(3);
+            sort_entries(&mut entries, &settings);
+            assert_eq!(listing_bytes(&entries, &settings), first);
+        }
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines[0], "file_00.txt  file_04.txt  file_08.txt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonical_resolves_symlinked_tree() {
+        let tmp = TempDir::new();
+        tmp.file("real/inner/file.txt", 1);
+        tmp.symlink("real/inner", "farm");
+        tmp.symlink("farm/../inner", "hop");
+        tmp.symlink("nowhere", "dangling");
+        let settings = Settings {
+            canonical: true,
+            classify: false,
+            ..test_settings()
+        };
+        let real = std::fs::canonicalize(tmp.path()).unwrap().join("real");
+
+        let tail = |path: &Path| {
+            let entry = Entry::from_path(path, Dereference::Never).unwrap();
+            format_entry_tail(&entry, &settings)
+        };
+        assert_eq!(
+            tail(&tmp.path().join("farm")),
+            format!(" -> real/inner  => {}", real.join("inner").display())
+        );
+        // the link goes through another link and ..
+        assert_eq!(
+            tail(&tmp.path().join("hop")),
+            format!(" -> farm/../inner  => {}", real.join("inner").display())
+        );
+        // a file inside the symlinked directory
+        assert_eq!(
+            tail(&tmp.path().join("farm/file.txt")),
+            format!("  => {}", real.join("inner/file.txt").display())
+        );
+        assert_eq!(
+            tail(&tmp.path().join("dangling")),
+            format!(
+                " -> nowhere  => {} (unresolved)",
+                tmp.path().join("dangling").display()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_target_is_colored_by_its_extension() {
+        let tmp = TempDir::new();
+        tmp.file("settings.yaml", 1);
+        tmp.dir("subdir");
+        let mut settings = Settings {
+            color: true,
+            bold: false,
+            ..test_settings()
         };
-        entries.push(entry);
+        // yellow for the target, magenta for the link itself
+        settings.color_by_extension.insert("yaml".to_string(), 33);
+        settings.color_by_extension.insert("lnk".to_string(), 35);
+        settings.color_by_filetype[FT_DIR] = 34;
+
+        let link_dest = |name: &str, target: &str| {
+            let entry = Entry::from_path(&tmp.symlink(target, name), Dereference::Never).unwrap();
+            format_link_dest(&entry, Path::new(target), &settings)
+        };
+        assert_eq!(
+            link_dest("config.lnk", "settings.yaml"),
+            "\x1b[33msettings.yaml\x1b[0m"
+        );
+        assert_eq!(link_dest("dir.lnk", "subdir"), "\x1b[34msubdir\x1b[0m");
+        // a dangling link has nothing to color by
+        assert_eq!(link_dest("gone.lnk", "gone.yaml"), "gone.yaml");
     }
 
-    sort_entries(&mut entries, settings);
-    show_listing(&entries, settings);
-
-    errors
-}
+    #[test]
+    fn hidden_only_over_mixed_listing() {
+        let tmp = TempDir::new();
+        tmp.file(".bashrc", 1);
+        tmp.file("visible.txt", 1);
+        tmp.dir(".config");
+        tmp.dir("docs");
+
+        let shown = |args: &[&str]| {
+            let (_, settings) = parse_args(args);
+            let entries = read_sorted(tmp.path(), &settings);
+            entries
+                .iter()
+                .filter(|x| filter_hidden(x, &settings))
+                .map(|x| display_name(x, &settings))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(shown(&["dir", "-1"]), ["docs", "visible.txt"]);
+        assert_eq!(
+            shown(&["dir", "-1", "--hidden-only"]),
+            [".config", ".bashrc"]
+        );
+        // --hidden-only wins over --all
+        assert_eq!(
+            shown(&["dir", "-1", "--hidden-only", "-a"]),
+            [".config", ".bashrc"]
+        );
+        assert_eq!(
+            shown(&["dir", "-1", "-a"]),
+            [".config", "docs", ".bashrc", "visible.txt"]
+        );
+    }
 
-// sort entries in-place
-fn sort_entries(entries: &mut [Entry], settings: &Settings) {
-    if settings.sort_by_size {
-        if settings.sort_reverse {
-            entries.sort_by_key(|x| std::cmp::Reverse(x.metadata.len()))
-        } else {
-            entries.sort_by_key(|x| x.metadata.len());
-        }
-    } else if settings.sort_by_time {
-        if settings.sort_reverse {
-            entries.sort_by_key(|x| std::cmp::Reverse(x.mtime()))
-        } else {
-            entries.sort_by_key(|x| x.mtime())
+    #[test]
+    fn format_size_fixed_across_magnitudes() {
+        let iec = [
+            (0, "     0 B  "),
+            (842, "   842 B  "),
+            (1023, "  1023 B  "),
+            (1024, "   1.0 KiB"),
+            (18_432, "  18.0 KiB"),
+            (1_048_575, "   1.0 MiB"),
+            (1_572_864, "   1.5 MiB"),
+            (3 << 40, "   3.0 TiB"),
+            (u64::MAX, "  16.0 EiB"),
+        ];
+        for (size, expected) in iec {
+            assert_eq!(format_size_fixed(size, false), expected);
         }
-    } else if settings.sort_by_extension {
-        if settings.sort_reverse {
-            entries.sort_by(|a, b| sorter_fn_extension(b, a));
-        } else {
-            entries.sort_by(sorter_fn_extension);
-        }
-    } else {
-        // sort by name, directories first
-        if settings.sort_reverse {
-            entries.sort_by(|a, b| sorter_dirs_first(b, a));
-        } else {
-            entries.sort_by(sorter_dirs_first);
+
+        let si = [
+            (999, "   999 B "),
+            (1000, "   1.0 kB"),
+            (999_999, "   1.0 MB"),
+            (123_456_789, " 123.5 MB"),
+            (u64::MAX, "  18.4 EB"),
+        ];
+        for (size, expected) in si {
+            assert_eq!(format_size_fixed(size, true), expected);
         }
     }
-}
 
-fn sorter_fn_extension(a: &Entry, b: &Entry) -> Ordering {
-    if a.metadata.is_dir() || b.metadata.is_dir() {
-        // do not treat dots in directory names as file extension
-        return sorter_dirs_first(a, b);
+    #[test]
+    fn aligned_placeholders_line_up_with_sizes() {
+        let tmp = TempDir::new();
+        tmp.dir("subdir");
+        tmp.file("file.txt", 18_432);
+        let (_, mut settings) = parse_args(&["dir", "-l", "--align-sizes"]);
+
+        let size_fields = |settings: &Settings| {
+            let entries = read_sorted(tmp.path(), settings);
+            entries
+                .iter()
+                .map(|x| format_size_field(x, settings))
+                .collect::<Vec<_>>()
+        };
+        // the placeholder ends where the numbers end
+        assert_eq!(size_fields(&settings), [" <DIR>    ", "  18.0 KiB"]);
+        settings.si = true;
+        assert_eq!(size_fields(&settings), [" <DIR>   ", "  18.4 kB"]);
+
+        // without --align-sizes, the placeholder is centered
+        settings.align_sizes = false;
+        assert_eq!(size_fields(&settings), [" <DIR>  ", "18.4 kB"]);
     }
 
-    if let Some(a_ext) = get_filename_ext(&a.name) {
-        let a_lower_ext = a_ext.to_lowercase();
-        if let Some(b_ext) = get_filename_ext(&b.name) {
-            let b_lower_ext = b_ext.to_lowercase();
-            let order = a_lower_ext.cmp(&b_lower_ext);
-            if order == Ordering::Equal {
-                return sorter_dirs_first(a, b);
-            }
-            return order;
-        } else {
-            // b_ext is None; a > b
-            return Ordering::Greater;
-        }
-    } else {
-        if let Some(_) = get_filename_ext(&b.name) {
-            // a_ext is None; a < b
-            return Ordering::Less;
-        }
-        // else both None
+    #[test]
+    fn expand_globs_matches_patterns() {
+        let tmp = TempDir::new();
+        tmp.file("main.rs", 1);
+        tmp.file("lib.rs", 1);
+        tmp.file("notes.txt", 1);
+        let pattern = |name: &str| tmp.path().join(name).to_string_lossy().to_string();
+
+        let matching = pattern("*.rs");
+        let (paths, errors) = expand_globs(&[&matching]);
+        assert_eq!(errors, 0);
+        assert_eq!(
+            paths,
+            [tmp.path().join("lib.rs"), tmp.path().join("main.rs")]
+        );
+
+        // like a shell, a pattern that matches nothing is an error
+        let nothing = pattern("*.md");
+        let (paths, errors) = expand_globs(&[&nothing, &matching]);
+        assert_eq!(errors, 1);
+        assert_eq!(paths.len(), 2);
+
+        // a literal path is kept as it is, even if it does not exist
+        let literal = pattern("missing.txt");
+        let (paths, errors) = expand_globs(&[&literal]);
+        assert_eq!(errors, 0);
+        assert_eq!(paths, [tmp.path().join("missing.txt")]);
     }
-    sorter_dirs_first(a, b)
-}
 
-fn sorter_dirs_first(a: &Entry, b: &Entry) -> Ordering {
-    if a.metadata.is_dir() {
-        if b.metadata.is_dir() {
-            let a_lower = a.name.to_string_lossy().to_lowercase();
-            let b_lower = b.name.to_string_lossy().to_lowercase();
-            a_lower.cmp(&b_lower)
-        } else {
-            Ordering::Less
-        }
-    } else {
-        // a is a file or something else, but not a directory
-        if b.metadata.is_dir() {
-            Ordering::Greater
-        } else {
-            let a_lower = a.name.to_string_lossy().to_lowercase();
-            let b_lower = b.name.to_string_lossy().to_lowercase();
-            a_lower.cmp(&b_lower)
-        }
+    // Returns local time at noon of the given date
+    fn local_noon(year: i32, month: u32, day: u32) -> DateTime<Local> {
+        use chrono::TimeZone;
+        Local
+            .with_ymd_and_hms(year, month, day, 12, 0, 0)
+            .single()
+            .unwrap()
     }
-}
 
-fn show_listing(entries: &[Entry], settings: &Settings) {
-    // show listing of all entries
-    // if not option --long (equals --wide), show wide listing
-    // if not option --all, do not show hidden files
+    #[test]
+    fn recent_days_boundary() {
+        let now = local_noon(2026, 1, 20);
+        let days_ago = |days: i64| now - chrono::Duration::days(days);
+        let (_, settings) = parse_args(&["dir", "-l", "--recent-days", "30"]);
+        assert_eq!(settings.recent_days, 30);
+
+        // files from last year show the time of day if they are recent enough
+        assert_eq!(
+            format_time_at(&days_ago(30), &now, &settings),
+            "Dec 21 12:00"
+        );
+        assert_eq!(
+            format_time_at(&days_ago(31), &now, &settings),
+            "Dec 20  2025"
+        );
 
-    let entries = if !settings.all {
-        entries
-            .iter()
-            .filter(|x| !x.is_hidden())
-            .collect::<Vec<&Entry>>()
-    } else {
-        entries.iter().collect::<Vec<&Entry>>()
-    };
+        // by default, the window is 90 days
+        let (_, settings) = parse_args(&["dir", "-l"]);
+        assert_eq!(
+            format_time_at(&days_ago(90), &now, &settings),
+            format!("{}", days_ago(90).format("%b %d %H:%M"))
+        );
+        assert_eq!(
+            format_time_at(&days_ago(91), &now, &settings),
+            format!("{}", days_ago(91).format("%b %d  %Y"))
+        );
 
-    if !settings.long {
-        show_wide_listing(&entries, settings);
-        return;
+        // the number of days must be positive
+        assert!(build_cli()
+            .try_get_matches_from(["dir", "--recent-days", "0"])
+            .is_err());
     }
 
-    for entry in entries {
-        println!("{}", format_entry(entry, settings));
+    #[test]
+    fn future_years_show_the_year() {
+        let now = local_noon(2026, 12, 20);
+        let settings = test_settings();
+
+        // later this year is shown like any time this year
+        assert_eq!(
+            format_time_at(&local_noon(2026, 12, 24), &now, &settings),
+            "Dec 24 12:00"
+        );
+        // next year, even if it is only days away
+        assert_eq!(
+            format_time_at(&local_noon(2027, 1, 2), &now, &settings),
+            "Jan 02  2027"
+        );
+        assert_eq!(
+            format_time_at(&local_noon(2099, 3, 15), &now, &settings),
+            "Mar 15  2099"
+        );
     }
-}
 
-fn show_wide_listing(entries: &[&Entry], settings: &Settings) {
-    // print in columns
-    // we have variable column widths
+    #[cfg(unix)]
+    #[test]
+    fn link_target_with_space_and_newline() {
+        let tmp = TempDir::new();
+        let link = tmp.symlink("my target\nfile", "link");
+        let entry = Entry::from_path(&link, Dereference::Never).unwrap();
+        let settings = Settings {
+            escape_nonprintable: true,
+            classify: false,
+            ..test_settings()
+        };
+        // the newline is escaped just like in names; the space is kept
+        assert_eq!(format_entry_tail(&entry, &settings), " -> my target\\nfile");
 
-    if entries.is_empty() {
-        return;
+        // the target is written as it is when not escaping
+        let settings = Settings {
+            escape_nonprintable: false,
+            ..settings
+        };
+        assert_eq!(format_entry_tail(&entry, &settings), " -> my target\nfile");
     }
 
-    let column_widths = determine_column_widths(entries, settings);
-    // dbg!(&column_widths);
-
-    // print entries
+    #[test]
+    fn indent_takes_away_columns() {
+        let tmp = ten_files();
+        let settings = Settings {
+            width: Some(40),
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        // three columns take 11 + 2 + 11 + 2 + 11 = 37 characters
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines[0].split_whitespace().count(), 3);
+
+        let indented = Settings {
+            indent: 4,
+            ..settings
+        };
+        assert_eq!(usable_width(&indented), 36);
+        let lines = wide_listing(&entries, &indented);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "    file_00.txt  file_05.txt");
+        assert!(lines.iter().all(|x| x.len() <= 40));
+    }
 
-    let mut num_lines = entries.len() / column_widths.len();
-    if entries.len() % column_widths.len() != 0 {
-        num_lines += 1;
+    #[cfg(unix)]
+    #[test]
+    fn legend_over_mixed_directory() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new();
+        tmp.dir("docs");
+        tmp.dir("src");
+        tmp.dir(".git");
+        tmp.file("a.txt", 1);
+        tmp.file("b.txt", 1);
+        tmp.file("c.txt", 1);
+        tmp.file(".profile", 1);
+        tmp.symlink("a.txt", "good");
+        tmp.symlink("missing", "broken");
+        let fifo = tmp.path().join("fifo");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) }, 0);
+
+        let legend = |args: &[&str]| {
+            let (_, settings) = parse_args(args);
+            let entries = read_sorted(tmp.path(), &settings);
+            let shown = entries
+                .iter()
+                .filter(|x| filter_hidden(x, &settings))
+                .collect::<Vec<&Entry>>();
+            format_legend(&shown)
+        };
+        assert_eq!(
+            legend(&["dir", "--legend"]),
+            "2 dirs, 3 files, 2 symlinks (1 broken), 1 fifo"
+        );
+        // hidden entries only count with --all
+        assert_eq!(
+            legend(&["dir", "--legend", "-a"]),
+            "3 dirs, 4 files, 2 symlinks (1 broken), 1 fifo"
+        );
     }
-    let num_lines = num_lines; // remove mut
 
-    for line in 0..num_lines {
-        let mut col = 0;
-        let mut i = line;
+    #[test]
+    fn hidden_names_widen_columns_only_when_shown() {
+        let tmp = TempDir::new();
+        for name in ["a", "b", "c", "d", "e", "f"] {
+            tmp.file(name, 1);
+        }
+        tmp.file(".a_rather_long_dotfile_name", 1);
+
+        let columns = |args: &[&str]| {
+            let (_, settings) = parse_args(args);
+            let entries = read_sorted(tmp.path(), &settings);
+            let shown = entries
+                .iter()
+                .filter(|x| filter_hidden(x, &settings))
+                .collect::<Vec<&Entry>>();
+            determine_column_widths(&shown, &settings, usable_width(&settings))
+        };
+        // six short names fit on one line
+        let widths = columns(&["dir", "-w", "--width", "40"]);
+        assert_eq!(widths, [3, 3, 3, 3, 3, 1]);
+        // the long dotfile name makes the first column wide,
+        // and then fewer columns fit
+        let widths = columns(&["dir", "-w", "-a", "--width", "40"]);
+        assert_eq!(widths, [29, 3, 3, 3, 0, 0]);
+    }
 
-        loop {
-            let entry = entries[i];
+    #[test]
+    fn no_type_fallback_leaves_plain_files_uncolored() {
+        let tmp = TempDir::new();
+        let plain = tmp.file("README", 1);
+        let source = tmp.file("main.rs", 1);
+        let (_, mut settings) = parse_args(&["dir", "--no-type-fallback"]);
+        settings.color = true;
+        settings.bold = false;
+        settings.color_by_filetype[FT_FILE] = 37;
+        settings.color_by_filetype[FT_DIR] = 34;
+        settings.color_by_extension.insert("rs".to_string(), 33);
+
+        let color = |path: &Path, settings: &Settings| {
+            let entry = Entry::from_path(path, Dereference::Never).unwrap();
+            colorize(&entry, settings)
+        };
+        assert_eq!(color(&plain, &settings), None);
+        assert_eq!(color(&source, &settings).unwrap(), "\x1b[33m");
+        // directories are not plain files; they keep their color
+        assert_eq!(color(tmp.path(), &settings).unwrap(), "\x1b[34m");
+
+        settings.type_fallback = true;
+        assert_eq!(color(&plain, &settings).unwrap(), "\x1b[37m");
+        assert_eq!(color(&source, &settings).unwrap(), "\x1b[33m");
+    }
 
-            let column_width = column_widths[col];
-            col += 1;
+    #[test]
+    fn accessed_time_drives_display_sort_and_filters() {
+        let tmp = TempDir::new();
+        let now = std::time::SystemTime::now();
+        // read_me was modified long ago but read recently; edited the other way around
+        let read_me = tmp.file("read_me", 1);
+        crate::testutil::set_times(&read_me, now - HOUR, now - 3 * DAY);
+        let edited = tmp.file("edited", 1);
+        crate::testutil::set_times(&edited, now - 3 * DAY, now - HOUR);
+        let other = TempDir::new();
+        let reference = other.file("reference", 1);
+        crate::testutil::set_times(&reference, now - DAY, now - DAY);
+        let newer_than = format!("--newer-than-file={}", reference.display());
+
+        let (_, modified) = parse_args(&["dir", "-l", "-t", "--full-time", &newer_than]);
+        let (_, accessed) =
+            parse_args(&["dir", "-l", "-t", "--full-time", "--accessed", &newer_than]);
+
+        // sorting is oldest first
+        let entries = read_sorted(tmp.path(), &modified);
+        assert_eq!(shown_names(&entries, &modified), ["read_me", "edited"]);
+        let entries = read_sorted(tmp.path(), &accessed);
+        assert_eq!(shown_names(&entries, &accessed), ["edited", "read_me"]);
+
+        let read_me = entries.iter().find(|x| x.name == "read_me").unwrap();
+        let shown_time = |settings: &Settings, time: std::time::SystemTime| {
+            assert_eq!(
+                format_column(read_me, Column::Time, settings),
+                format_full_time(&time.into(), settings.time_precision)
+            );
+        };
+        shown_time(&modified, now - 3 * DAY);
+        shown_time(&accessed, now - HOUR);
+
+        // the age is that of the time that is shown
+        let age = file_age(read_me, &accessed);
+        assert!(age >= HOUR && age < 2 * HOUR);
+        assert!(file_age(read_me, &modified) >= 3 * DAY);
+
+        // and so is the time of the reference file
+        let passes = |settings: &Settings| {
+            entries
+                .iter()
+                .filter(|x| filter_time(x, settings))
+                .map(|x| x.name.to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(passes(&modified), ["edited"]);
+        assert_eq!(passes(&accessed), ["read_me"]);
+    }
 
-            print!("{}", format_wide_entry(entry, settings));
+    #[cfg(unix)]
+    #[test]
+    fn dereferenced_time_drives_age_and_filters() {
+        let tmp = TempDir::new();
+        let now = std::time::SystemTime::now();
+        let target = tmp.file("target", 1);
+        crate::testutil::set_times(&target, now - DAY, now - 3 * DAY);
+        let links = TempDir::new();
+        links.symlink(&target.to_string_lossy(), "link");
+        let reference = tmp.file("reference", 1);
+        crate::testutil::set_times(&reference, now - 2 * DAY, now - 2 * DAY);
+        let older_than = format!("--older-than-file={}", reference.display());
+
+        let link_age = |args: &[&str]| {
+            let (_, settings) = parse_args(args);
+            let (entries, _) = list_dir(links.path(), &settings).unwrap();
+            let link = &entries[0];
+            (file_age(link, &settings), filter_time(link, &settings))
+        };
+        // the link itself was made just now
+        let (age, passes) = link_age(&["dir", &older_than]);
+        assert!(age < HOUR);
+        assert!(!passes);
+        let (age, passes) = link_age(&["dir", "--dereference=time", &older_than]);
+        assert!(age >= 3 * DAY);
+        assert!(passes);
+        let (age, passes) = link_age(&["dir", "--dereference=time", "--accessed", &older_than]);
+        assert!(age >= DAY && age < 2 * DAY);
+        assert!(!passes);
+    }
 
-            i += num_lines;
-            if i >= entries.len() {
-                break;
-            }
-            if col >= column_widths.len() || column_widths[col] == 0 {
-                break;
-            }
+    #[cfg(unix)]
+    #[test]
+    fn owner_filter_with_current_user() {
+        let tmp = TempDir::new();
+        tmp.file("a.txt", 1);
+        tmp.file("b.txt", 1);
+        let uid = unsafe { libc::getuid() };
+        let gid = unsafe { libc::getgid() };
+
+        let passing = |args: &[&str]| {
+            let (_, settings) = parse_args(args);
+            let entries = read_sorted(tmp.path(), &settings);
+            entries
+                .iter()
+                .filter(|x| filter_owner(x, &settings))
+                .count()
+        };
+        assert_eq!(passing(&["dir", "--owner", &uid.to_string()]), 2);
+        assert_eq!(passing(&["dir", "--owner", &(uid + 1).to_string()]), 0);
+        assert_eq!(passing(&["dir", "--group", &gid.to_string()]), 2);
+        assert_eq!(
+            passing(&[
+                "dir",
+                "--owner",
+                &uid.to_string(),
+                "--group",
+                &(gid + 1).to_string()
+            ]),
+            0
+        );
+        // by name, too
+        if let Some(name) = lookup_user_name(uid) {
+            assert_eq!(passing(&["dir", "--owner", &name]), 2);
+        }
+    }
 
-            let spacer = column_width - display_width(entry, settings);
-            if spacer > 0 {
-                print!("{:<spacer$}", " ");
-            }
+    #[cfg(unix)]
+    #[test]
+    fn perm_filter_finds_setuid_and_world_writable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        for (name, mode) in [
+            ("plain", 0o644),
+            ("setuid", 0o4755),
+            ("shared", 0o666),
+            ("script", 0o755),
+        ] {
+            let path = tmp.file(name, 1);
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
         }
-        println!("");
+        let public = tmp.dir("public");
+        std::fs::set_permissions(&public, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        let passing = |perm: &str| {
+            let (_, settings) = parse_args(&["dir", "--perm", perm]);
+            let entries = read_sorted(tmp.path(), &settings);
+            entries
+                .iter()
+                .filter(|x| filter_perm(x, &settings))
+                .map(|x| x.name.to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(passing("-4000"), ["setuid"]);
+        assert_eq!(passing("/u+s"), ["setuid"]);
+        assert_eq!(passing("-o+w"), ["public", "shared"]);
+        assert_eq!(passing("/0002"), ["public", "shared"]);
+        assert_eq!(passing("644"), ["plain"]);
+        // either setuid or world-writable
+        assert_eq!(passing("/u+s,o+w"), ["public", "setuid", "shared"]);
     }
-}
 
-#[derive(Debug)]
-struct ColumnInfo {
-    valid: bool,
-    line_length: usize,
-    column_widths: Vec<usize>,
-}
+    #[test]
+    fn only_hidden_files() {
+        let tmp = TempDir::new();
+        tmp.file(".profile", 1);
+        tmp.file(".bashrc", 1);
+        tmp.dir(".config");
+
+        let (_, settings) = parse_args(&["dir", "--show-hidden-count"]);
+        let entries = read_sorted(tmp.path(), &settings);
+        assert!(!entries.iter().any(|x| filter_hidden(x, &settings)));
+        assert_eq!(count_hidden(&entries, &settings), 3);
+
+        // nothing is left out when they are shown
+        let (_, settings) = parse_args(&["dir", "--show-hidden-count", "-a"]);
+        assert_eq!(count_hidden(&entries, &settings), 0);
+    }
 
-impl ColumnInfo {
-    const SPACER: usize = 2;
+    #[test]
+    fn posixly_correct_defaults_and_overrides() {
+        let posix = |args: &[&str]| {
+            let matches = build_cli().try_get_matches_from(args).unwrap();
+            let mut settings = Settings::new();
+            apply_posixly_correct(&mut settings);
+            apply_args(&matches, &mut settings);
+            settings
+        };
 
-    fn new() -> ColumnInfo {
-        ColumnInfo {
-            valid: true,
-            line_length: 0,
-            column_widths: Vec::new(),
-        }
+        let settings = posix(&["dir"]);
+        assert!(!settings.color);
+        assert!(!settings.classify);
+        assert!(settings.c_collation);
+        assert_eq!(settings.block_size, Some(512));
+        assert!(!settings.full_time);
+        assert_eq!(settings.recent_days, 182);
+
+        // explicit flags still win
+        let settings = posix(&[
+            "dir",
+            "--color",
+            "--classify",
+            "--full-time",
+            "--recent-days",
+            "7",
+            "--iec",
+        ]);
+        assert!(settings.color);
+        assert!(settings.classify);
+        assert_eq!(settings.block_size, None);
+        assert!(settings.full_time);
+        assert_eq!(settings.recent_days, 7);
     }
-}
 
-// Returns width of filename on screen
-fn display_width(entry: &Entry, settings: &Settings) -> usize {
-    let mut width = entry.name.to_string_lossy().chars().count();
-    if let Some(_) = classify(entry, settings) {
-        width += 1;
+    #[test]
+    fn json_wins_over_color() {
+        let (_, settings) = parse_args(&["dir", "--json", "--color"]);
+        assert!(settings.json);
+        assert!(!settings.color);
+        let (_, settings) = parse_args(&["dir", "--color"]);
+        assert!(settings.color);
     }
-    width
-}
 
-// Returns minimum column width of all entries
-fn determine_min_column_width(entries: &[&Entry], settings: &Settings, term_width: usize) -> usize {
-    let mut min_width = term_width;
+    #[test]
+    fn classify_only_configured_filetypes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        let sub = Entry::from_path(&tmp.dir("sub"), Dereference::Never).unwrap();
+        let plain = Entry::from_path(&tmp.file("plain", 1), Dereference::Never).unwrap();
+        let link = Entry::from_path(&tmp.symlink("plain", "link"), Dereference::Never).unwrap();
+        let path = tmp.file("run", 1);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let run = Entry::from_path(&path, Dereference::Never).unwrap();
+
+        let _lock = crate::testutil::ENV_LOCK.lock().unwrap();
+        let configured = |config: serde_json::Value| Settings {
+            long: false,
+            ..load_config_data(&config, Path::new("dir.json"))
+        };
 
-    for entry in entries.iter() {
-        let w = display_width(*entry, settings);
-        min_width = std::cmp::min(min_width, w + ColumnInfo::SPACER);
+        let settings = configured(serde_json::json!({"classify": ["directory", "exec"]}));
+        assert_eq!(classify(&sub, &settings), Some("/"));
+        assert_eq!(classify(&run, &settings), Some("*"));
+        assert_eq!(classify(&link, &settings), None);
+        assert_eq!(classify(&plain, &settings), None);
+
+        // classify true gives every type its indicator
+        let settings = configured(serde_json::json!({"classify": true}));
+        assert_eq!(classify(&link, &settings), Some("@"));
+
+        // unknown names are config errors
+        let (_, _, errors) = load_config_classify(
+            &serde_json::json!(["directory", "no-such-type", 1]),
+            Path::new("dir.json"),
+        );
+        assert_eq!(errors, 2);
     }
-    min_width
-}
 
-// Returns vec of column widths
-fn determine_column_widths(entries: &[&Entry], settings: &Settings) -> Vec<usize> {
-    /*
-        The procedure used here to determine the variable column widths
-        is the same as what GNU coreutils `ls` does
-        which is try to fit filenames in columns, while checking the line length
-        If the line length goes over the terminal width, then that's invalid;
-        you can't have as many columns
-        If it does fit, try fitting the next file
-    */
+    #[test]
+    fn dir_count_skips_what_can_not_be_read() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        tmp.file("sub/a", 1);
+        tmp.file("sub/b", 1);
+        tmp.file("sub/.hidden", 1);
+        let sub = tmp.path().join("sub");
+        let all = Settings {
+            all: true,
+            ..test_settings()
+        };
+        assert_eq!(count_dir_entries(&sub, &test_settings()), Some(2));
+        assert_eq!(count_dir_entries(&sub, &all), Some(3));
+
+        // without search permission, the entries can not be stat'ed,
+        // but the names can still be counted
+        fs::set_permissions(&sub, fs::Permissions::from_mode(0o600)).unwrap();
+        let counts = (
+            count_dir_entries(&sub, &test_settings()),
+            count_dir_entries(&sub, &all),
+        );
+        fs::set_permissions(&sub, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(counts, (Some(2), Some(3)));
 
-    // determine terminal width
-    let term_width = if let Some((terminal_size::Width(w), terminal_size::Height(_))) =
-        terminal_size::terminal_size()
-    {
-        w as usize
-    } else {
-        // note, getting the terminal size will fail when output is redirected
-        80usize
-    };
+        assert_eq!(count_dir_entries(&tmp.path().join("gone"), &all), None);
+    }
 
-    if entries.len() <= 1 {
-        return vec![term_width];
+    #[test]
+    fn dir_size_leaves_out_unreadable_subdirectory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        tmp.file("top/a", 100);
+        tmp.file("top/locked/b", 50);
+        tmp.file("top/open/c", 7);
+        let locked = tmp.path().join("top/locked");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        let size = dir_size(&tmp.path().join("top"), &test_settings());
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // root may read the locked directory anyway
+        let expected = if unsafe { libc::geteuid() } == 0 {
+            157
+        } else {
+            107
+        };
+        assert_eq!(size.unwrap(), expected);
+
+        // without the directory itself, there is no total
+        assert!(dir_size(&tmp.path().join("gone"), &test_settings()).is_err());
     }
 
-    // number of possible columns
-    let min_width = determine_min_column_width(entries, settings, term_width);
-    let num_possible = term_width / min_width;
-    if num_possible <= 1 {
-        return vec![term_width];
+    #[test]
+    fn included_patterns_are_tried_in_alphabetical_order() {
+        let tmp = TempDir::new();
+        fs::write(
+            tmp.path().join("base.json"),
+            r#"{"extension": {"Make*": "red", "*.lock": "yellow"}}"#,
+        )
+        .unwrap();
+        let config_file = tmp.path().join("dir.json");
+        let config = serde_json::json!({
+            "include": "base.json",
+            "extension": {"M*": "green", "*.lock": "blue"},
+        });
+
+        let _lock = crate::testutil::ENV_LOCK.lock().unwrap();
+        let settings = load_config_data(&config, &config_file);
+        let color = |name: &str| color_by_pattern(OsStr::new(name), &settings);
+        // "M*" sorts before "Make*", although it is merged after it
+        assert_eq!(color("Makefile"), color_by_name("green"));
+        // this file overrides the included pattern
+        assert_eq!(color("Cargo.lock"), color_by_name("blue"));
+        assert_eq!(
+            settings
+                .color_by_pattern
+                .iter()
+                .map(|(p, _)| p.as_str())
+                .collect::<Vec<&str>>(),
+            ["*.lock", "M*", "Make*"]
+        );
     }
-    let mut column_info = Vec::<ColumnInfo>::with_capacity(num_possible);
-    /*
-        make a triangular data structure;
-        column_info[0] has 1 column widths
-        column_info[1] has 2 column widths
-        column_info[2] has 3 column widths
-        and so on
-    */
-    for u in 0..num_possible {
-        column_info.push(ColumnInfo::new());
-        column_info
-            .last_mut()
-            .expect("unexpected memory error")
-            .column_widths = vec![0; u + 1];
+
+    #[test]
+    fn wide_listing_measures_wide_characters() {
+        let tmp = TempDir::new();
+        tmp.dir("データ");
+        tmp.file("a.txt", 1);
+        tmp.file("日本語.txt", 1);
+        let mut settings = Settings {
+            width: Some(22),
+            ..test_settings()
+        };
+        settings.indicator_by_filetype[FT_DIR] = "📁".to_string();
+        let entries = read_sorted(tmp.path(), &settings);
+        // CJK characters and the emoji take two columns each
+        let widths = entries
+            .iter()
+            .map(|e| display_width(e, &settings))
+            .collect::<Vec<usize>>();
+        assert_eq!(widths, [8, 5, 10]);
+
+        let lines = wide_listing(&entries, &settings);
+        assert_eq!(lines, ["データ📁  日本語.txt", "a.txt"]);
+        assert!(lines.iter().all(|line| line.width() <= 22));
     }
 
-    // determine column widths by fitting entries in
+    #[test]
+    fn escaped_names_are_measured_as_shown() {
+        let tmp = TempDir::new();
+        tmp.file("表\tx\u{1}.txt", 1);
+        let settings = Settings {
+            escape_nonprintable: true,
+            ..test_settings()
+        };
+        let entries = read_sorted(tmp.path(), &settings);
+        let shown = display_name(&entries[0], &settings);
+        assert_eq!(shown, "表\\tx\\x01.txt");
+        // the escapes take more columns than the raw control characters
+        assert_eq!(display_width(&entries[0], &settings), 13);
+        assert_eq!(display_width(&entries[0], &settings), shown.width());
+    }
 
-    for (n, entry) in entries.iter().enumerate() {
-        for i in 0..num_possible {
-            if !column_info[i].valid {
-                continue;
-            }
-            let col = n / ((entries.len() + i) / (i + 1));
-            let mut width = display_width(*entry, settings);
-            if col != i {
-                width += ColumnInfo::SPACER;
-            }
-            let width = width; // remove mut
+    #[test]
+    fn dereference_list_picks_attributes_from_the_target() {
+        let tmp = TempDir::new();
+        let now = std::time::SystemTime::now();
+        let target = tmp.file("target", 1000);
+        crate::testutil::set_times(&target, now - 3 * DAY, now - 3 * DAY);
+        let links = TempDir::new();
+        let target_path = target.to_string_lossy().to_string();
+        links.symlink(&target_path, "link");
+        // the link itself holds the path of its target, and was made just now
+        let link_size = target_path.len() as u64;
+
+        let link_attrs = |list: &str| {
+            let arg = format!("--dereference={}", list);
+            let (_, settings) = parse_args(&["dir", &arg]);
+            let (entries, _) = list_dir(links.path(), &settings).unwrap();
+            let link = &entries[0];
+            let age = now
+                .duration_since(link.time(TimeSource::Modified).into())
+                .unwrap_or_default();
+            (
+                metadata_filetype(&link.metadata),
+                link.size(),
+                age >= 3 * DAY,
+            )
+        };
+        assert_eq!(link_attrs("type"), (FT_FILE, link_size, false));
+        assert_eq!(link_attrs("size"), (FT_SYMLINK, 1000, false));
+        assert_eq!(link_attrs("type,size"), (FT_FILE, 1000, false));
+        assert_eq!(link_attrs("type,time"), (FT_FILE, link_size, true));
+        assert_eq!(link_attrs("type,size,time"), (FT_FILE, 1000, true));
+    }
 
-            if width >= column_info[i].column_widths[col] {
-                // filename is longer than the column's width;
-                // column needs adjusting
-                let old_column_width = column_info[i].column_widths[col];
-                column_info[i].column_widths[col] = width;
-                column_info[i].line_length += width - old_column_width;
-                // does it still fit onscreen?
-                column_info[i].valid = column_info[i].line_length < term_width;
-            }
-        }
+    #[test]
+    fn one_per_line_keeps_classify() {
+        let tmp = TempDir::new();
+        tmp.dir("docs");
+        tmp.file("a.txt", 1);
+
+        let listing = |args: &[&str]| {
+            let (_, settings) = parse_args(args);
+            let entries = read_sorted(tmp.path(), &settings);
+            String::from_utf8(listing_bytes(&entries, &settings)).unwrap()
+        };
+        // -1 only changes the layout; the indicators stay
+        assert_eq!(listing(&["dir", "-1", "--classify"]), "docs/\na.txt\n");
+        assert_eq!(listing(&["dir", "--classify", "-1"]), "docs/\na.txt\n");
+        assert_eq!(listing(&["dir", "-1", "--no-classify"]), "docs\na.txt\n");
+
+        // the names can only be streamed when there are no indicators
+        let (_, settings) = parse_args(&["dir", "-1", "--sort=none", "--classify"]);
+        assert!(!can_stream_names(&settings));
+        let (_, settings) = parse_args(&["dir", "-1", "--sort=none", "--no-classify"]);
+        assert!(can_stream_names(&settings));
     }
 
-    // the highest number of columns is the one that is valid
-    let mut col = 0;
-    for i in (0..num_possible).rev() {
-        if column_info[i].valid {
-            col = i;
-            break;
-        }
+    #[test]
+    fn excluded_directories_are_listed_but_not_descended() {
+        let tmp = TempDir::new();
+        tmp.file("node_modules/pkg/index.js", 1);
+        tmp.file("src/main.rs", 1);
+        tmp.file("build.cache/obj", 1);
+
+        let (_, settings) = parse_args(&[
+            "dir",
+            "-R",
+            "--exclude-dir",
+            "node_modules",
+            "--exclude-dir",
+            "*.cache",
+        ]);
+        let entries = read_sorted(tmp.path(), &settings);
+        // the excluded directories are still in their parent's listing
+        assert_eq!(
+            shown_names(&entries, &settings),
+            ["build.cache", "node_modules", "src"]
+        );
+        let descended = entries
+            .iter()
+            .filter(|x| should_descend(x, &settings))
+            .map(|x| x.name.to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(descended, ["src"]);
     }
-    // return column widths
-    // NOTE the vec of columns may be larger than the actual number of columns
-    // displayed onscreen; the rightmost columns may have width zero
-    column_info[col].column_widths.clone()
-}
 
-fn list_dir(path: &Path) -> Result<Vec<Entry>, io::Error> {
-    let mut entries = Vec::new();
+    #[test]
+    fn config_include_merges_and_reports_errors() {
+        let tmp = TempDir::new();
+        let config_file = tmp.path().join("dir.json");
+        fs::write(&config_file, "{}").unwrap();
+        fs::write(
+            tmp.path().join("base.json"),
+            r#"{"include": "theme/colors.json", "extension": {"*.rs": "red"}}"#,
+        )
+        .unwrap();
+        tmp.dir("theme");
+        fs::write(
+            tmp.path().join("theme/colors.json"),
+            r#"{"extension": {"*.md": "green"}}"#,
+        )
+        .unwrap();
+
+        let include = |name: &str| {
+            let mut settings = test_settings();
+            let mut visited = vec![fs::canonicalize(&config_file).unwrap()];
+            let errors = load_config_include(
+                &serde_json::json!(name),
+                &config_file,
+                &mut settings,
+                &mut visited,
+            );
+            (errors, settings)
+        };
 
-    for dir_entry in fs::read_dir(path)? {
-        // an fs::DirEntry holds an open file descriptor to the directory
-        // we don't want that ... so therefore I convert it to a custom Entry type
-        // the Entry holds all the same attributes; name, metadata, linkdest (if it is a symbolic link)
-        // but also (attempts) has an easier interface
-        // Mind that the conversion may error, in which case we print the error
-        // and skip this entry
+        // nested includes are relative to the file that includes them
+        let (errors, settings) = include("base.json");
+        assert_eq!(errors, 0);
+        let color = |name: &str| color_by_pattern(OsStr::new(name), &settings);
+        assert_eq!(color("main.rs"), color_by_name("red"));
+        assert_eq!(color("README.md"), color_by_name("green"));
 
-        let entry = match dir_entry {
-            Ok(d) => {
-                match Entry::from_dir_entry(&d) {
-                    Ok(x) => x,
-                    Err(err) => {
-                        // failed to read this single entry
-                        eprintln!("{}: {}", &d.path().to_string_lossy(), err);
-                        continue;
-                    }
-                }
-            }
-            Err(e) => return Err(e),
+        let (errors, _) = include("missing.json");
+        assert_eq!(errors, 1);
+
+        fs::write(tmp.path().join("loop.json"), r#"{"include": "dir.json"}"#).unwrap();
+        let (errors, _) = include("loop.json");
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn size_column_per_filetype() {
+        let tmp = TempDir::new();
+        tmp.file("sub/one.txt", 1);
+        tmp.file("file.txt", 100);
+        tmp.symlink("file.txt", "link");
+        fs::hard_link(tmp.path().join("file.txt"), tmp.path().join("other")).unwrap();
+
+        let _lock = crate::testutil::ENV_LOCK.lock().unwrap();
+        let config = serde_json::json!({
+            "size_column": {"directory": "blank", "file": "links", "Symlink": "placeholder"},
+        });
+        let settings = Settings {
+            color: false,
+            ..load_config_data(&config, Path::new("dir.json"))
         };
-        entries.push(entry);
+        let entries = read_sorted(tmp.path(), &settings);
+        let size_fields = entries
+            .iter()
+            .map(|x| format_size_field(x, &settings).trim().to_string())
+            .collect::<Vec<String>>();
+        // sub, file.txt, link, other
+        assert_eq!(size_fields, ["", "2", "<LINK>", "2"]);
+
+        let mut size_fields = default_size_fields();
+        let errors = load_config_size_column(
+            &serde_json::json!({"file": "huge", "nosuchtype": "size", "fifo": 1}),
+            Path::new("dir.json"),
+            &mut size_fields,
+        );
+        assert_eq!(errors, 3);
+        assert_eq!(size_fields, default_size_fields());
+    }
+
+    #[test]
+    fn ls_colors_are_the_defaults() {
+        let mut settings = test_settings();
+        load_ls_colors(
+            "rs=0:di=01;34:ln=01;36:*.tar=01;31:*.TGZ=31:*.[ch]=33:ex=01;32:mi=38;5;9:bogus",
+            &mut settings,
+        );
+        assert_eq!(settings.color_by_filetype[FT_DIR], 34);
+        assert_eq!(settings.color_by_filetype[FT_SYMLINK], 36);
+        assert_eq!(settings.color_by_mode[FM_EXEC], 32);
+        assert_eq!(settings.color_by_extension.get("tar"), Some(&31));
+        assert_eq!(settings.color_by_extension.get("tgz"), Some(&31));
+        // glob patterns are not extensions
+        assert_eq!(settings.color_by_extension.get("[ch]"), None);
+
+        // bold is left out; 256 colors do not fit
+        assert_eq!(parse_ls_color("01;34"), Some(34));
+        assert_eq!(parse_ls_color("01"), None);
+        assert_eq!(parse_ls_color("38;5;208"), None);
+        assert_eq!(parse_ls_color("xyz"), None);
+
+        // the config file takes precedence
+        let _lock = crate::testutil::ENV_LOCK.lock().unwrap();
+        std::env::set_var("LS_COLORS", "di=01;34:ln=01;36");
+        let config = serde_json::json!({"filetype": {"directory": "red"}});
+        let settings = load_config_data(&config, Path::new("dir.json"));
+        std::env::remove_var("LS_COLORS");
+        assert_eq!(
+            settings.color_by_filetype[FT_DIR],
+            color_by_name("red").unwrap()
+        );
+        assert_eq!(settings.color_by_filetype[FT_SYMLINK], 36);
     }
-    Ok(entries)
 }
 
 // EOB
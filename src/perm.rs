@@ -0,0 +1,107 @@
+//
+//  dir         WJ124
+//  perm.rs
+//
+
+// --perm filters entries by permission bits, like `find -perm`
+// The mode is octal (4000) or symbolic (go+w, u=rwx,g=rx)
+//
+//   MODE    the permission bits are exactly MODE
+//   -MODE   all of the bits in MODE are set
+//   /MODE   any of the bits in MODE is set
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PermMatch {
+    Exact,
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PermFilter {
+    how: PermMatch,
+    mode: u32,
+}
+
+impl PermFilter {
+    pub fn parse(spec: &str) -> Result<PermFilter, String> {
+        let (how, mode_spec) = if let Some(rest) = spec.strip_prefix('-') {
+            (PermMatch::All, rest)
+        } else if let Some(rest) = spec.strip_prefix('/') {
+            (PermMatch::Any, rest)
+        } else {
+            (PermMatch::Exact, spec)
+        };
+
+        let mode = if !mode_spec.is_empty() && mode_spec.chars().all(|c| c.is_digit(8)) {
+            u32::from_str_radix(mode_spec, 8)
+                .ok()
+                .filter(|x| *x <= 0o7777)
+                .ok_or_else(|| "invalid mode".to_string())?
+        } else {
+            parse_symbolic(mode_spec).ok_or_else(|| "invalid mode".to_string())?
+        };
+        Ok(PermFilter { how, mode })
+    }
+
+    // Returns true if the permission bits match
+    pub fn matches(&self, mode: u32) -> bool {
+        let mode = mode & 0o7777;
+        match self.how {
+            PermMatch::Exact => mode == self.mode,
+            PermMatch::All => mode & self.mode == self.mode,
+            // like find, no bits at all matches anything
+            PermMatch::Any => self.mode == 0 || mode & self.mode != 0,
+        }
+    }
+}
+
+// parse symbolic mode like "go+w" or "u=rwx,g=rx"
+// The clauses are applied in turn, starting from no bits at all
+fn parse_symbolic(spec: &str) -> Option<u32> {
+    let mut mode = 0u32;
+
+    for clause in spec.split(',') {
+        let op_idx = clause.find(['+', '-', '='])?;
+        let (who_spec, rest) = clause.split_at(op_idx);
+        let mut chars = rest.chars();
+        let op = chars.next()?;
+
+        let mut who = 0u32;
+        for c in who_spec.chars() {
+            who |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+
+        let mut perms = 0u32;
+        for c in chars {
+            perms |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return None,
+            };
+        }
+        let bits = who & perms;
+
+        match op {
+            '+' => mode |= bits,
+            '-' => mode &= !bits,
+            '=' => mode = (mode & !who) | bits,
+            _ => return None,
+        }
+    }
+    Some(mode)
+}
+
+// EOB
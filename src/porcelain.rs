@@ -0,0 +1,117 @@
+//
+//  dir         WJ124
+//  porcelain.rs
+//
+
+// --porcelain output format for scripts
+//
+// This format is a contract; it must not change across versions.
+// One line per entry, with these tab-separated fields:
+//
+//   type    single character: - d l p s b c
+//   mode    permission bits as four octal digits, like 0755
+//   size    size in bytes
+//   mtime   modification time in seconds since the epoch (UTC)
+//   name    the name; backslash, tab and newline are escaped as \\ \t \n
+//
+// There is no color, no classify indicator, and no header
+
+use crate::entry::Entry;
+use crate::{metadata_filetype, FT_BLOCKDEV, FT_CHARDEV, FT_DIR, FT_FIFO, FT_SOCK, FT_SYMLINK};
+
+pub fn format_entry(entry: &Entry, name: &str) -> String {
+    let mtime = entry.mtime().timestamp();
+
+    format!(
+        "{}\t{:04o}\t{}\t{}\t{}",
+        type_char(entry),
+        mode(entry),
+        entry.size(),
+        mtime,
+        escape(name)
+    )
+}
+
+fn type_char(entry: &Entry) -> char {
+    match metadata_filetype(&entry.metadata) {
+        FT_DIR => 'd',
+        FT_SYMLINK => 'l',
+        FT_FIFO => 'p',
+        FT_SOCK => 's',
+        FT_BLOCKDEV => 'b',
+        FT_CHARDEV => 'c',
+        _ => '-',
+    }
+}
+
+// Returns the permission bits
+#[cfg(unix)]
+pub fn mode(entry: &Entry) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    entry.metadata.permissions().mode() & 0o7777
+}
+
+// there are no permission bits; make something up that looks alike
+#[cfg(not(unix))]
+pub fn mode(entry: &Entry) -> u32 {
+    let mode = if entry.metadata.is_dir() {
+        0o755
+    } else {
+        0o644
+    };
+    if entry.metadata.permissions().readonly() {
+        mode & 0o555
+    } else {
+        mode
+    }
+}
+
+fn escape(name: &str) -> String {
+    let mut buf = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\\' => buf.push_str("\\\\"),
+            '\t' => buf.push_str("\\t"),
+            '\n' => buf.push_str("\\n"),
+            _ => buf.push(c),
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Dereference;
+    use crate::testutil::{set_mtime, TempDir};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[cfg(unix)]
+    #[test]
+    fn field_order() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new();
+        let path = tmp.file("file.txt", 5);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        set_mtime(&path, UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+
+        let entry = Entry::from_path(&path, Dereference::Never).unwrap();
+        assert_eq!(
+            format_entry(&entry, "file.txt"),
+            "-\t0640\t5\t1000000000\tfile.txt"
+        );
+
+        let entry = Entry::from_path(&tmp.dir("sub"), Dereference::Never).unwrap();
+        assert!(format_entry(&entry, "sub").starts_with("d\t"));
+    }
+
+    #[test]
+    fn name_is_escaped() {
+        assert_eq!(escape("a\tb\\c\nd"), "a\\tb\\\\c\\nd");
+        assert_eq!(escape("plain name"), "plain name");
+    }
+}
+
+// EOB
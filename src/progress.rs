@@ -0,0 +1,110 @@
+//
+//  dir         WJ124
+//  progress.rs
+//
+
+// progress indicator for slow recursive walks
+// It is shown on stderr, and only if stderr is a terminal
+// The walks count what they read as they go (also the worker threads
+// of --dir-size), so that the indicator keeps turning while a large
+// directory is being read. It stays on screen until something is printed;
+// whatever prints output must clear it first
+
+use lazy_static::lazy_static;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const INTERVAL: Duration = Duration::from_millis(100);
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct State {
+    num_dirs: usize,
+    num_entries: usize,
+    spinner: usize,
+    shown: bool,
+    last_update: Instant,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        num_dirs: 0,
+        num_entries: 0,
+        spinner: 0,
+        shown: false,
+        last_update: Instant::now(),
+    });
+}
+
+pub fn enable() {
+    if !io::stderr().is_terminal() {
+        return;
+    }
+    // the indicator does not show up at all for quick listings
+    STATE.lock().unwrap().last_update = Instant::now();
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// count one more directory scanned
+pub fn add_dir() {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    state.num_dirs += 1;
+    redraw(&mut state);
+}
+
+// count one more entry read
+pub fn add_entry() {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    state.num_entries += 1;
+    redraw(&mut state);
+}
+
+// The indicator is redrawn only every so often
+fn redraw(state: &mut State) {
+    let now = Instant::now();
+    if now.duration_since(state.last_update) < INTERVAL {
+        return;
+    }
+    state.last_update = now;
+
+    state.spinner = (state.spinner + 1) % SPINNER.len();
+    let mut stderr = io::stderr();
+    let _ = write!(
+        stderr,
+        "\r{} {} directories, {} entries scanned",
+        SPINNER[state.spinner], state.num_dirs, state.num_entries
+    );
+    let _ = stderr.flush();
+    state.shown = true;
+}
+
+// remove the indicator from the screen
+// It is drawn again on the next update
+pub fn clear() {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    if !state.shown {
+        return;
+    }
+    let mut stderr = io::stderr();
+    let _ = write!(stderr, "\r\x1b[K");
+    let _ = stderr.flush();
+    state.shown = false;
+}
+
+// EOB
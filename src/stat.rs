@@ -0,0 +1,177 @@
+//
+//  dir         WJ124
+//  stat.rs
+//
+
+// --stat shows everything that is known about a path, like stat(1)
+// One field per line, as "key: value"; with --json the fields go into
+// the JSON document, one object per path. Fields that are not known
+// on this platform show "-" (or null in JSON)
+
+use crate::entry::Entry;
+use crate::{
+    display_link_dest, filetype_name, format_column, format_full_time, json, metadata_filetype,
+    porcelain, report_error, Column, Settings, FT_BLOCKDEV, FT_CHARDEV,
+};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+// show all fields of every path
+// Returns number of errors
+pub(crate) fn show_stat(paths: &[PathBuf], settings: &Settings) -> u32 {
+    let mut errors = 0;
+    let mut first = true;
+
+    for path in paths.iter() {
+        let entry = match Entry::from_path(path, settings.dereference) {
+            Ok(x) => x,
+            Err(e) => {
+                report_error(path, &e, settings);
+                errors += 1;
+                continue;
+            }
+        };
+        let fields = stat_fields(path, &entry, settings);
+
+        if settings.json {
+            let obj = fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect::<serde_json::Map<String, Value>>();
+            json::add_value(Value::Object(obj));
+            continue;
+        }
+
+        if !first {
+            println!();
+        }
+        first = false;
+        for (key, value) in fields.iter() {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Null => "-".to_string(),
+                _ => value.to_string(),
+            };
+            println!("{:<13}{}", format!("{}:", key), value);
+        }
+    }
+
+    if settings.json {
+        json::print_document();
+    }
+    errors
+}
+
+// Returns the fields in the order in which they are shown
+fn stat_fields(path: &Path, entry: &Entry, settings: &Settings) -> Vec<(&'static str, Value)> {
+    // times are in RFC 3339 for scripts, and with nanoseconds for humans
+    let time_value = |dt: Option<chrono::DateTime<chrono::Local>>| match dt {
+        Some(dt) if settings.json => json!(dt.to_rfc3339()),
+        Some(dt) => json!(format_full_time(&dt, 9)),
+        None => Value::Null,
+    };
+
+    // JSON has escaping of its own
+    let path_value = |path: &Path| {
+        if settings.json {
+            path.to_string_lossy().to_string()
+        } else {
+            display_link_dest(path, settings)
+        }
+    };
+
+    let mut fields = vec![
+        ("path", json!(path_value(path))),
+        (
+            "type",
+            json!(filetype_name(metadata_filetype(&entry.metadata))),
+        ),
+        ("size", json!(entry.size())),
+        ("allocated", json!(entry.allocated_size())),
+        ("mode", json!(format!("{:04o}", porcelain::mode(entry)))),
+        (
+            "permissions",
+            json!(format_column(entry, Column::Perms, settings)),
+        ),
+    ];
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        fields.push((
+            "owner",
+            json!(format_column(entry, Column::Author, settings)),
+        ));
+        fields.push(("uid", json!(entry.metadata.uid())));
+        fields.push((
+            "group",
+            json!(format_column(entry, Column::Group, settings)),
+        ));
+        fields.push(("gid", json!(entry.metadata.gid())));
+    }
+    #[cfg(not(unix))]
+    {
+        fields.push(("owner", Value::Null));
+        fields.push(("uid", Value::Null));
+        fields.push(("group", Value::Null));
+        fields.push(("gid", Value::Null));
+    }
+
+    fields.push(("inode", json!(entry.inode())));
+    fields.push(("links", json!(entry.nlink())));
+    // only device files have a device number
+    let filetype = metadata_filetype(&entry.metadata);
+    if filetype == FT_BLOCKDEV || filetype == FT_CHARDEV {
+        if let Some((major, minor)) = entry.device_number() {
+            fields.push(("device", json!(format!("{},{}", major, minor))));
+        }
+    }
+    fields.push(("modified", time_value(Some(entry.mtime()))));
+    fields.push(("accessed", time_value(entry.atime())));
+    fields.push(("changed", time_value(entry.ctime())));
+    fields.push((
+        "link_dest",
+        json!(entry.link_dest.as_deref().map(path_value)),
+    ));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Dereference;
+    use crate::testutil::TempDir;
+
+    // Returns value of field
+    fn field(fields: &[(&'static str, Value)], key: &str) -> Value {
+        fields.iter().find(|(k, _)| *k == key).unwrap().1.clone()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn json_has_raw_names() {
+        let tmp = TempDir::new();
+        let link = tmp.symlink("red\x1b[31m", "link\x1b[31m");
+        let entry = Entry::from_path(&link, Dereference::Never).unwrap();
+
+        let settings = Settings {
+            escape_nonprintable: true,
+            json: true,
+            ..Settings::new()
+        };
+        let fields = stat_fields(&link, &entry, &settings);
+        assert_eq!(field(&fields, "path"), json!(link.to_string_lossy()));
+        assert_eq!(field(&fields, "link_dest"), json!("red\x1b[31m"));
+
+        // on a terminal, the escape character is shown harmlessly
+        let settings = Settings {
+            escape_nonprintable: true,
+            ..Settings::new()
+        };
+        let fields = stat_fields(&link, &entry, &settings);
+        assert_eq!(field(&fields, "link_dest"), json!("red\\x1b[31m"));
+    }
+}
+
+// EOB
@@ -0,0 +1,126 @@
+//
+//  dir         WJ124
+//  stats.rs
+//
+
+// counters and timers for --stats
+// They are shown on stderr after the listing, to see where the time goes
+// When --stats is off, nothing is measured; the counters cost one
+// relaxed atomic add each
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static DIRS_READ: AtomicU64 = AtomicU64::new(0);
+static ENTRIES_READ: AtomicU64 = AtomicU64::new(0);
+static IO_NANOS: AtomicU64 = AtomicU64::new(0);
+static FORMAT_NANOS: AtomicU64 = AtomicU64::new(0);
+
+// the internal caches that are counted
+#[derive(Debug, Clone, Copy)]
+pub enum Cache {
+    Permissions,
+    Owner,
+    Group,
+}
+
+impl Cache {
+    const ALL: [Cache; 3] = [Cache::Permissions, Cache::Owner, Cache::Group];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Cache::Permissions => "permissions",
+            Cache::Owner => "owner",
+            Cache::Group => "group",
+        }
+    }
+}
+
+static CACHE_HITS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+static CACHE_MISSES: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Returns the current time, but only when measuring
+pub fn start() -> Option<Instant> {
+    if ENABLED.load(Ordering::Relaxed) {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+// count a directory that was read, and its entries
+pub fn add_dir(num_entries: usize) {
+    DIRS_READ.fetch_add(1, Ordering::Relaxed);
+    ENTRIES_READ.fetch_add(num_entries as u64, Ordering::Relaxed);
+}
+
+// count entries that were not read from a directory (command-line arguments)
+pub fn add_entries(num_entries: usize) {
+    ENTRIES_READ.fetch_add(num_entries as u64, Ordering::Relaxed);
+}
+
+// add time spent reading directories and metadata
+pub fn add_io_time(started: Option<Instant>) {
+    if let Some(started) = started {
+        IO_NANOS.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+// add time spent formatting and printing
+pub fn add_format_time(started: Option<Instant>) {
+    if let Some(started) = started {
+        FORMAT_NANOS.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+pub fn cache_hit(cache: Cache) {
+    CACHE_HITS[cache as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn cache_miss(cache: Cache) {
+    CACHE_MISSES[cache as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+// show the statistics on stderr
+pub fn print(total: Duration) {
+    let io_time = Duration::from_nanos(IO_NANOS.load(Ordering::Relaxed));
+    let format_time = Duration::from_nanos(FORMAT_NANOS.load(Ordering::Relaxed));
+
+    eprintln!("dir: stats:");
+    eprintln!("  directories read: {}", DIRS_READ.load(Ordering::Relaxed));
+    eprintln!(
+        "  entries read:     {}",
+        ENTRIES_READ.load(Ordering::Relaxed)
+    );
+    eprintln!("  time total:       {:.3} ms", millis(total));
+    eprintln!("  time reading:     {:.3} ms", millis(io_time));
+    eprintln!("  time formatting:  {:.3} ms", millis(format_time));
+
+    for cache in Cache::ALL.iter() {
+        let hits = CACHE_HITS[*cache as usize].load(Ordering::Relaxed);
+        let misses = CACHE_MISSES[*cache as usize].load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        if lookups == 0 {
+            continue;
+        }
+        eprintln!(
+            "  {} cache: {} hits, {} misses ({:.1}% hit rate)",
+            cache.name(),
+            hits,
+            misses,
+            hits as f64 * 100.0 / lookups as f64
+        );
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+// EOB
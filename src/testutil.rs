@@ -0,0 +1,90 @@
+//
+//  dir         WJ124
+//  testutil.rs
+//
+
+// helpers for the tests
+// every test works in a scratch directory of its own,
+// which is removed again when it goes out of scope
+// not every test uses every helper
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// tests that change environment variables must not run at the same time
+pub static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("dir-test-{}-{}", std::process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("failed to create scratch directory");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // create a file of the given size; the name may contain subdirectories
+    pub fn file(&self, name: &str, size: usize) -> PathBuf {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create directory");
+        }
+        fs::write(&path, vec![b'x'; size]).expect("failed to create file");
+        path
+    }
+
+    pub fn dir(&self, name: &str) -> PathBuf {
+        let path = self.path.join(name);
+        fs::create_dir_all(&path).expect("failed to create directory");
+        path
+    }
+
+    #[cfg(unix)]
+    pub fn symlink(&self, target: &str, name: &str) -> PathBuf {
+        let path = self.path.join(name);
+        std::os::unix::fs::symlink(target, &path).expect("failed to create symlink");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn set_mtime(path: &Path, time: SystemTime) {
+    let file = fs::File::options()
+        .write(true)
+        .open(path)
+        .or_else(|_| fs::File::open(path))
+        .expect("failed to open file");
+    file.set_modified(time).expect("failed to set mtime");
+}
+
+pub fn set_times(path: &Path, accessed: SystemTime, modified: SystemTime) {
+    let file = fs::File::options()
+        .write(true)
+        .open(path)
+        .expect("failed to open file");
+    let times = fs::FileTimes::new()
+        .set_accessed(accessed)
+        .set_modified(modified);
+    file.set_times(times).expect("failed to set times");
+}
+
+// EOB
@@ -0,0 +1,104 @@
+//
+//  dir         WJ124
+//  tree.rs
+//
+
+use crate::entry::Entry;
+use crate::progress;
+use crate::{
+    filter_entry, format_link_dest, format_wide_entry, list_dir, matches_any, print_dir_header,
+    print_indent, report_error, should_descend, sort_entries, Settings,
+};
+use std::fs;
+use std::path::Path;
+
+// connectors for drawing the tree
+const BRANCH: &str = "├── ";
+const LAST_BRANCH: &str = "└── ";
+const PIPE: &str = "│   ";
+const SPACE: &str = "    ";
+
+// show directory as a tree, like `tree`
+// With --dirs-tree only the directories are shown, like `tree -d`
+// Returns number of errors
+pub(crate) fn show_tree(dir_path: &Path, settings: &Settings) -> u32 {
+    print_dir_header(dir_path, settings);
+    show_subtree(dir_path, "", 1, settings)
+}
+
+fn show_subtree(dir_path: &Path, prefix: &str, depth: usize, settings: &Settings) -> u32 {
+    let (mut entries, mut errors) = match list_dir(dir_path, settings) {
+        Ok(x) => x,
+        Err(e) => {
+            report_error(dir_path, &e, settings);
+            return 1;
+        }
+    };
+
+    sort_entries(&mut entries, settings);
+
+    let max_depth_reached = settings.depth.is_some_and(|max| depth >= max);
+    let shown = entries
+        .iter()
+        .map(|x| (x, !max_depth_reached && should_descend(x, settings)))
+        .filter(|(x, descend)| is_shown(x, *descend, settings))
+        .collect::<Vec<(&Entry, bool)>>();
+
+    for (idx, (entry, descend)) in shown.iter().enumerate() {
+        let last = idx == shown.len() - 1;
+        let connector = if last { LAST_BRANCH } else { BRANCH };
+        progress::clear();
+        print_indent(settings);
+        println!(
+            "{}{}{}",
+            prefix,
+            connector,
+            format_tree_entry(entry, settings)
+        );
+
+        // symlinked directories are shown, but not descended into
+        if *descend {
+            let sub_prefix = format!("{}{}", prefix, if last { SPACE } else { PIPE });
+            errors += show_subtree(&entry.path(), &sub_prefix, depth + 1, settings);
+        }
+    }
+    errors
+}
+
+// Returns true if the entry is shown in the tree
+// The directories that are descended into hold the tree together; the
+// filters that select files, like --match and --newer-than, do not hide
+// them (like `tree -P`), but hidden and ignored directories are left out
+// With --dirs-tree, the directories are what is listed, and every filter applies
+fn is_shown(entry: &Entry, descend: bool, settings: &Settings) -> bool {
+    if !settings.tree {
+        return is_dir_or_link_to_dir(entry) && filter_entry(entry, settings);
+    }
+    if descend {
+        return !matches_any(&settings.ignore_patterns, &entry.name, settings);
+    }
+    filter_entry(entry, settings)
+}
+
+fn format_tree_entry(entry: &Entry, settings: &Settings) -> String {
+    let mut buf = format_wide_entry(entry, settings);
+    if let Some(link_dest) = &entry.link_dest {
+        buf.push_str(&format!(
+            " -> {}",
+            format_link_dest(entry, link_dest, settings)
+        ));
+    }
+    buf
+}
+
+fn is_dir_or_link_to_dir(entry: &Entry) -> bool {
+    if entry.metadata.is_dir() {
+        return true;
+    }
+    entry.metadata.is_symlink()
+        && fs::metadata(entry.path())
+            .map(|x| x.is_dir())
+            .unwrap_or(false)
+}
+
+// EOB
@@ -0,0 +1,78 @@
+//
+//  dir         WJ124
+//  watch.rs
+//
+
+use notify::{PollWatcher, RecursiveMode, Watcher};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+// events often come in bursts; wait for things to settle down
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// show listing, and show it again whenever any of the paths changes
+// When an interval is given, the paths are polled rather than
+// relying on filesystem events; this also works on network filesystems
+// This function only returns on error; stop it with Ctrl-C
+pub fn watch<F>(
+    paths: &[PathBuf],
+    recursive: bool,
+    interval: Option<Duration>,
+    mut show_listing: F,
+) -> Result<(), notify::Error>
+where
+    F: FnMut(),
+{
+    let (tx, rx) = mpsc::channel();
+
+    let mut watcher: Box<dyn Watcher> = match interval {
+        Some(interval) => Box::new(PollWatcher::new(
+            tx,
+            notify::Config::default().with_poll_interval(interval),
+        )?),
+        None => Box::new(notify::recommended_watcher(tx)?),
+    };
+
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    for path in paths {
+        watcher.watch(path, mode)?;
+    }
+
+    loop {
+        clear_screen();
+        show_listing();
+
+        // wait for a change
+        match rx.recv() {
+            Ok(event) => {
+                event?;
+            }
+            // the watcher is gone
+            Err(_) => return Ok(()),
+        }
+        // drain the burst of events
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+            event?;
+        }
+    }
+}
+
+fn clear_screen() {
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "\x1b[H\x1b[2J");
+    let _ = stdout.flush();
+}
+
+// Returns true if watch mode is possible
+// It only makes sense on a terminal
+pub fn can_watch() -> bool {
+    io::stdout().is_terminal()
+}
+
+// EOB
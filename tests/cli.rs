@@ -0,0 +1,383 @@
+//
+//  dir         WJ124
+//  cli.rs
+//
+
+// tests that run the dir binary itself
+
+#[path = "../src/testutil.rs"]
+mod testutil;
+
+use std::path::Path;
+use std::process::{Command, Output};
+use testutil::TempDir;
+
+// Returns command for running dir, without any config from the environment
+fn dir_command() -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_dir"));
+    for name in [
+        "DIR_CONFIG",
+        "DIR_CONFIG_HOME",
+        "LS_COLORS",
+        "POSIXLY_CORRECT",
+        "COLUMNS",
+    ] {
+        cmd.env_remove(name);
+    }
+    cmd
+}
+
+// Returns stdout of running dir with arguments
+fn run(cmd: &mut Command) -> String {
+    let output = cmd.output().expect("failed to run dir");
+    assert_success(&output);
+    String::from_utf8(output.stdout).expect("output is not UTF-8")
+}
+
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "dir failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn write_config(path: &Path) {
+    std::fs::write(
+        path,
+        r#"{"layout": "long", "classify": false, "color": true, "recent_days": 1}"#,
+    )
+    .expect("failed to write config file");
+}
+
+#[test]
+fn no_config_ignores_config_and_environment() {
+    let tmp = TempDir::new();
+    tmp.file("listing/a.txt", 1);
+    tmp.dir("listing/sub");
+    let listing = tmp.path().join("listing");
+    let config_file = tmp.path().join("dir.json");
+    write_config(&config_file);
+
+    let configured = |no_config: bool| {
+        let mut cmd = dir_command();
+        cmd.env("DIR_CONFIG", &config_file)
+            .env("LS_COLORS", "di=01;31")
+            .env("POSIXLY_CORRECT", "1")
+            .args(["--width", "80"]);
+        if no_config {
+            cmd.arg("--no-config");
+        }
+        run(cmd.arg(&listing))
+    };
+    let plain = run(dir_command()
+        .env("DIR_CONFIG_HOME", tmp.path().join("nonexistent"))
+        .args(["--width", "80", "--no-config"])
+        .arg(&listing));
+
+    assert_eq!(configured(true), plain);
+    // and the config file does make a difference
+    assert_ne!(configured(false), plain);
+}
+
+#[test]
+fn relative_paths_in_two_level_tree() {
+    let tmp = TempDir::new();
+    tmp.file("top.txt", 1);
+    tmp.file("sub/mid.txt", 1);
+    tmp.file("sub/deeper/low.txt", 1);
+
+    let output = run(dir_command()
+        .args(["--no-config", "-R", "--relative", "-1", "--no-classify"])
+        .arg(tmp.path()));
+
+    let lines = output.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "sub",
+            "top.txt",
+            "sub/deeper",
+            "sub/mid.txt",
+            "sub/deeper/low.txt"
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn glob_option_expands_patterns() {
+    let tmp = TempDir::new();
+    tmp.file("main.rs", 1);
+    tmp.file("lib.rs", 1);
+    tmp.file("notes.txt", 1);
+    let pattern = tmp.path().join("*.rs");
+
+    let output =
+        run(dir_command()
+            .current_dir(tmp.path())
+            .args(["--no-config", "-1", "--glob", "*.rs"]));
+    assert_eq!(output, "lib.rs\nmain.rs\n");
+
+    // without --glob, the pattern is taken literally
+    let output = dir_command()
+        .args(["--no-config", "-1"])
+        .arg(&pattern)
+        .output()
+        .expect("failed to run dir");
+    assert!(!output.status.success());
+
+    // a pattern that matches nothing is an error, like in a shell
+    let output = dir_command()
+        .current_dir(tmp.path())
+        .args(["--no-config", "-1", "--glob", "*.md"])
+        .output()
+        .expect("failed to run dir");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("*.md: no matches found"));
+}
+
+#[test]
+fn stats_count_streamed_listing() {
+    let tmp = TempDir::new();
+    tmp.file("a.txt", 1);
+    tmp.file("b.txt", 1);
+    tmp.file(".hidden", 1);
+
+    // -1 --no-classify --sort=none streams the names straight from the directory
+    let output = dir_command()
+        .args([
+            "--no-config",
+            "-1",
+            "--no-classify",
+            "--sort=none",
+            "--stats",
+        ])
+        .arg(tmp.path())
+        .output()
+        .expect("failed to run dir");
+    assert_success(&output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("directories read: 1\n"), "{}", stderr);
+    // hidden entries are read too, even though they are not shown
+    assert!(stderr.contains("entries read:     3\n"), "{}", stderr);
+}
+
+#[test]
+fn dired_offsets_point_at_the_names() {
+    let tmp = TempDir::new();
+    tmp.file("plain.txt", 1);
+    tmp.file("café.txt", 1);
+    tmp.dir("sub");
+
+    let output = run(dir_command()
+        .args(["--no-config", "-l", "--dired", "-R"])
+        .arg(tmp.path()));
+    let trailer = output
+        .lines()
+        .find_map(|x| x.strip_prefix("//DIRED//"))
+        .expect("no //DIRED// trailer");
+    let offsets = trailer
+        .split_whitespace()
+        .map(|x| x.parse::<usize>().unwrap())
+        .collect::<Vec<usize>>();
+    let names = offsets
+        .chunks(2)
+        .map(|x| &output[x[0]..x[1]])
+        .collect::<Vec<&str>>();
+    assert_eq!(names, ["sub", "café.txt", "plain.txt"]);
+}
+
+#[test]
+fn flat_list_of_full_paths() {
+    let tmp = TempDir::new();
+    tmp.file("top.txt", 1);
+    tmp.file("sub/mid.txt", 1);
+    tmp.file("sub/deeper/low.txt", 1);
+
+    let output = run(dir_command()
+        .args([
+            "--no-config",
+            "-R",
+            "--no-headers",
+            "--full-path",
+            "-1",
+            "--no-classify",
+        ])
+        .arg(tmp.path()));
+
+    // no headers, and no blank lines in between the directories
+    let expected = [
+        "sub",
+        "top.txt",
+        "sub/deeper",
+        "sub/mid.txt",
+        "sub/deeper/low.txt",
+    ]
+    .iter()
+    .map(|x| format!("{}\n", tmp.path().join(x).display()))
+    .collect::<String>();
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn tree_applies_filters() {
+    let tmp = TempDir::new();
+    tmp.file("main.rs", 1);
+    tmp.file("notes.txt", 1);
+    tmp.file(".hidden.rs", 1);
+    tmp.file("src/lib.rs", 1);
+    tmp.file("src/data.json", 1);
+    tmp.file("target/out.rs", 1);
+
+    let tree = |args: &[&str]| {
+        let output = run(dir_command()
+            .args(["--no-config", "--tree"])
+            .args(args)
+            .arg(tmp.path()));
+        // leave out the header
+        output
+            .lines()
+            .skip(1)
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // directories hold the tree together; --match only selects files
+    assert_eq!(
+        tree(&["--match", "*.rs"]),
+        [
+            "├── src/",
+            "│   └── lib.rs",
+            "├── target/",
+            "│   └── out.rs",
+            "└── main.rs"
+        ]
+    );
+    // but an ignored directory is left out altogether
+    assert_eq!(
+        tree(&["--match", "*.rs", "--ignore", "target"]),
+        ["├── src/", "│   └── lib.rs", "└── main.rs"]
+    );
+    assert_eq!(
+        tree(&["--hidden-only"]),
+        ["├── src/", "├── target/", "└── .hidden.rs"]
+    );
+
+    // the per-listing lines do not fit in a tree
+    for flag in ["--summary", "--legend", "--show-hidden-count"] {
+        for tree_flag in ["--tree", "--dirs-tree"] {
+            let output = dir_command()
+                .args(["--no-config", tree_flag, flag])
+                .arg(tmp.path())
+                .output()
+                .expect("failed to run dir");
+            assert!(!output.status.success(), "{} {}", tree_flag, flag);
+        }
+    }
+}
+
+#[test]
+fn diff_with_unreadable_directory_prints_no_differences() {
+    let tmp = TempDir::new();
+    tmp.file("a/one.txt", 1);
+    tmp.file("a/two.txt", 1);
+
+    let output = dir_command()
+        .args(["--no-config", "--diff"])
+        .arg(tmp.path().join("a"))
+        .arg(tmp.path().join("missing"))
+        .output()
+        .expect("failed to run dir");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing"));
+}
+
+#[cfg(unix)]
+#[test]
+fn unknown_owner_is_an_error() {
+    for option in ["--owner", "--group"] {
+        let output = dir_command()
+            .args(["--no-config", option, "no-such-name-for-dir-tests"])
+            .output()
+            .expect("failed to run dir");
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.starts_with(&format!(
+                "dir: {} no-such-name-for-dir-tests: no such",
+                option
+            )),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn invalid_perm_is_an_error() {
+    let output = dir_command()
+        .args(["--no-config", "--perm", "u+q"])
+        .output()
+        .expect("failed to run dir");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr, "dir: --perm u+q: invalid mode\n");
+}
+
+#[test]
+fn hidden_count_of_only_hidden_files() {
+    let tmp = TempDir::new();
+    tmp.file(".profile", 1);
+    tmp.file(".bashrc", 1);
+
+    let hidden_count = |args: &[&str]| {
+        run(dir_command()
+            .args(["--no-config", "--show-hidden-count"])
+            .args(args)
+            .arg(tmp.path()))
+    };
+    // the note is all there is
+    assert_eq!(hidden_count(&["-1"]), "(2 hidden)\n");
+    assert_eq!(hidden_count(&["-l"]), "(2 hidden)\n");
+    assert_eq!(hidden_count(&["-1", "-a"]), ".bashrc\n.profile\n");
+}
+
+#[test]
+fn posixly_correct_defaults_and_overrides() {
+    let tmp = TempDir::new();
+    tmp.file("listing/a", 1000);
+    tmp.file("listing/B", 24);
+    let listing = tmp.path().join("listing");
+
+    let posix = |args: &[&str]| {
+        run(dir_command()
+            .env("POSIXLY_CORRECT", "1")
+            .env("DIR_CONFIG_HOME", tmp.path().join("nonexistent"))
+            .args(["-1", "--summary"])
+            .args(args)
+            .arg(&listing))
+    };
+    // byte order, and the total in 512-byte blocks
+    assert_eq!(posix(&[]), "B\na\n2 files, 0 dirs, 2 blocks total\n");
+    // explicit flags still win
+    assert_eq!(posix(&["--iec"]), "B\na\n2 files, 0 dirs, 1.0 KiB total\n");
+    assert_eq!(posix(&["--si"]), "B\na\n2 files, 0 dirs, 1.0 kB total\n");
+}
+
+#[test]
+fn json_wins_over_color() {
+    let tmp = TempDir::new();
+    tmp.file("listing/a.txt", 1);
+    tmp.dir("listing/sub");
+
+    let output = run(dir_command()
+        .args(["--no-config", "--json", "--color"])
+        .arg(tmp.path().join("listing")));
+    assert!(!output.contains('\x1b'), "escape sequence in {:?}", output);
+    assert!(output.contains("\"name\": \"sub\""));
+}
+
+// EOB